@@ -0,0 +1,194 @@
+use candid::Principal;
+use ic_cdk::api::time;
+use ic_scalable_canister::ic_scalable_misc::models::paged_response_models::PagedResponse;
+use shared::group_model::{Group, GroupAuditRecord, GroupFieldChange};
+
+use crate::store::AUDIT_LOG;
+
+// Append-only log of the changes to the groups
+// the keys are `{group_identifier}:{position}`, the zero padded position of the record within the log of the group
+// so a page of the log can be read with a range scan and the number of records follows from the last key
+pub struct AuditLog;
+
+impl AuditLog {
+    // Method to add a change of a group to the log, nothing is logged if no field changed
+    pub fn add_record(
+        caller: Principal,
+        method: &str,
+        group_identifier: Principal,
+        before: Option<&Group>,
+        after: Option<&Group>,
+    ) {
+        let changes = Self::get_changes(before, after);
+        if changes.is_empty() {
+            return;
+        }
+
+        AUDIT_LOG.with(|log| {
+            let key = Self::get_key(group_identifier, Self::get_count(group_identifier));
+            log.borrow_mut().insert(
+                key,
+                GroupAuditRecord {
+                    group_identifier,
+                    caller,
+                    method: method.to_string(),
+                    changes,
+                    timestamp: time(),
+                },
+            );
+        });
+    }
+
+    // Method to remove the log of a group, used when the group is permanently removed
+    pub fn remove_records(group_identifier: Principal) {
+        let prefix = format!("{}:", group_identifier);
+        AUDIT_LOG.with(|log| {
+            let keys: Vec<String> = log
+                .borrow()
+                .range(prefix.clone()..)
+                .take_while(|(key, _)| key.starts_with(&prefix))
+                .map(|(key, _)| key)
+                .collect();
+
+            let mut log = log.borrow_mut();
+            for key in keys {
+                log.remove(&key);
+            }
+        });
+    }
+
+    // Method to get a page of the log of a group, newest first
+    // only the records of the requested page are read, a page past the last page returns the last page
+    pub fn get_paged_records(
+        group_identifier: Principal,
+        limit: usize,
+        page: usize,
+    ) -> PagedResponse<GroupAuditRecord> {
+        let total = Self::get_count(group_identifier) as usize;
+        let limit = limit.min(total);
+        let number_of_pages = match limit {
+            0 => 0,
+            _ => total.div_ceil(limit),
+        };
+
+        if page == 0 || number_of_pages == 0 {
+            return PagedResponse {
+                page,
+                limit,
+                total,
+                number_of_pages,
+                data: vec![],
+            };
+        }
+
+        let page = page.min(number_of_pages);
+
+        // The records are stored oldest first, so the first page holds the last positions
+        let end = total - (page - 1) * limit;
+        let start = end.saturating_sub(limit);
+
+        let mut records: Vec<GroupAuditRecord> = AUDIT_LOG.with(|log| {
+            log.borrow()
+                .range(
+                    Self::get_key(group_identifier, start as u64)
+                        ..Self::get_key(group_identifier, end as u64),
+                )
+                .map(|(_, record)| record)
+                .collect()
+        });
+        records.reverse();
+
+        PagedResponse {
+            page,
+            limit,
+            total,
+            number_of_pages,
+            data: records,
+        }
+    }
+
+    fn get_key(group_identifier: Principal, position: u64) -> String {
+        format!("{}:{:020}", group_identifier, position)
+    }
+
+    // The number of records of a group is the position of its last record plus one
+    // `;` is the character after `:`, so the last key below `{group_identifier};` is the last record of the group
+    fn get_count(group_identifier: Principal) -> u64 {
+        let prefix = format!("{}:", group_identifier);
+        AUDIT_LOG.with(|log| {
+            log.borrow()
+                .iter_upper_bound(&format!("{};", group_identifier))
+                .next()
+                .and_then(|(key, _)| {
+                    key.strip_prefix(&prefix)
+                        .and_then(|position| position.parse::<u64>().ok())
+                })
+                .map_or(0, |position| position + 1)
+        })
+    }
+
+    // Method to get the fields that are different before and after the change
+    fn get_changes(before: Option<&Group>, after: Option<&Group>) -> Vec<GroupFieldChange> {
+        let before_fields = before.map(Self::get_fields);
+        let after_fields = after.map(Self::get_fields);
+
+        let fields: Vec<&str> = before_fields
+            .as_ref()
+            .or(after_fields.as_ref())
+            .map(|fields| fields.iter().map(|(field, _)| *field).collect())
+            .unwrap_or_default();
+
+        fields
+            .into_iter()
+            .enumerate()
+            .filter_map(|(index, field)| {
+                let before = before_fields.as_ref().map(|fields| fields[index].1.clone());
+                let after = after_fields.as_ref().map(|fields| fields[index].1.clone());
+                if before == after {
+                    return None;
+                }
+
+                Some(GroupFieldChange {
+                    field: field.to_string(),
+                    before,
+                    after,
+                })
+            })
+            .collect()
+    }
+
+    // Method to get the text value of the fields that are logged, the update and creation times are left out
+    fn get_fields(group: &Group) -> Vec<(&'static str, String)> {
+        // Sort the maps so the same values always give the same text
+        let mut member_count: Vec<(&Principal, &usize)> = group.member_count.iter().collect();
+        member_count.sort();
+        let mut wallets: Vec<(&Principal, &String)> = group.wallets.iter().collect();
+        wallets.sort();
+
+        vec![
+            ("name", group.name.clone()),
+            ("description", group.description.clone()),
+            ("website", group.website.clone()),
+            ("location", format!("{:?}", group.location)),
+            ("privacy", format!("{:?}", group.privacy)),
+            (
+                "privacy_gated_type_amount",
+                format!("{:?}", group.privacy_gated_type_amount),
+            ),
+            ("owner", group.owner.to_string()),
+            ("matrix_space_id", group.matrix_space_id.clone()),
+            ("image", format!("{:?}", group.image)),
+            ("banner_image", format!("{:?}", group.banner_image)),
+            ("tags", format!("{:?}", group.tags)),
+            ("roles", format!("{:?}", group.roles)),
+            ("member_count", format!("{:?}", member_count)),
+            ("wallets", format!("{:?}", wallets)),
+            ("is_deleted", group.is_deleted.to_string()),
+            ("deleted_on", format!("{:?}", group.deleted_on)),
+            (
+                "pending_ownership_transfer",
+                format!("{:?}", group.pending_ownership_transfer),
+            ),
+        ]
+    }
+}
@@ -5,7 +5,7 @@ use ic_scalable_canister::{ic_scalable_misc, store::Data};
 use ic_stable_structures::StableBTreeMap;
 use shared::group_model::Group;
 
-use crate::store::{ENTRIES, ENTRIES_MEMORY_ID, MEMORY_MANAGER, STABLE_DATA};
+use crate::store::{Store, ENTRIES, ENTRIES_MEMORY_ID, MEMORY_MANAGER, STABLE_DATA};
 
 //
 #[update(guard = "is_owner")]
@@ -40,6 +40,8 @@ pub fn restore_data() {
             e.borrow_mut().insert(entry.0.to_string(), entry.1.clone());
         });
     });
+
+    Store::rebuild_indexes();
 }
 
 // #[update(guard = "is_owner")]
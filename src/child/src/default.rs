@@ -8,7 +8,7 @@ use ic_cdk::{
             provisional::CanisterIdRecord,
         },
     },
-    caller, id, init, query, update,
+    caller, id, init, post_upgrade, query, update,
 };
 use ic_scalable_canister::ic_scalable_misc::{
     enums::api_error_type::ApiError,
@@ -16,26 +16,13 @@ use ic_scalable_canister::ic_scalable_misc::{
 };
 use ic_scalable_canister::{ic_methods, store::Data};
 
-use crate::{
-    store::{ENTRIES, STABLE_DATA},
-    IDENTIFIER_KIND,
-};
+use crate::store::{Store, ENTRIES, STABLE_DATA};
 
 // This call get triggered when a new canister is spun up
 // the data is passed along to the new canister as a byte array
 #[update(guard = "auth")]
 async fn add_entry_by_parent(entry: Vec<u8>) -> Result<(), ApiError> {
-    STABLE_DATA.with(|data| {
-        ENTRIES.with(|entries| {
-            Data::add_entry_by_parent(
-                data,
-                entries,
-                caller(),
-                entry,
-                Some(IDENTIFIER_KIND.to_string()),
-            )
-        })
-    })
+    Store::add_entry_by_parent(caller(), entry)
 }
 
 // Method to accept cycles when send to this canister
@@ -83,6 +70,15 @@ pub fn init(parent: Principal, name: String, identifier: usize) {
     STABLE_DATA.with(|data| {
         ic_methods::init(data, parent, name, identifier);
     });
+
+    Store::start_timers();
+}
+
+// Timers are not kept on upgrade so they are started again
+// the indexes are stable and kept, an upgrade that changes an index calls `rebuild_indexes` afterwards
+#[post_upgrade]
+pub fn post_upgrade() {
+    Store::start_timers();
 }
 
 // Method used to save the candid interface to a file
@@ -0,0 +1,210 @@
+use candid::Principal;
+use ic_cdk::api::time;
+use ic_scalable_canister::ic_scalable_misc::models::identifier_model::Identifier;
+use shared::group_model::{GroupJobKind, RoleGrant};
+
+use crate::{jobs::Jobs, store::ROLE_GRANTS};
+
+// Max number of expired grants that are revoked per run
+static REVOKE_BATCH_SIZE: usize = 100;
+
+// Temporary roles of the members, a grant is kept until the role is revoked
+// the keys are `{group_identifier}:{member_identifier}:{role_name}`
+pub struct RoleGrants;
+
+impl RoleGrants {
+    // Method to add or replace the grant of a role to a member
+    pub fn add_grant(grant: RoleGrant) {
+        ROLE_GRANTS.with(|grants| {
+            grants.borrow_mut().insert(
+                Self::get_key(
+                    grant.group_identifier,
+                    grant.member_identifier,
+                    &grant.role_name,
+                ),
+                grant,
+            )
+        });
+    }
+
+    // Method to remove the grant of a role, used when the role is assigned without an expiry
+    pub fn remove_grant(
+        group_identifier: Principal,
+        member_identifier: Principal,
+        role_name: &str,
+    ) {
+        ROLE_GRANTS.with(|grants| {
+            grants.borrow_mut().remove(&Self::get_key(
+                group_identifier,
+                member_identifier,
+                role_name,
+            ))
+        });
+    }
+
+    // Method to get the temporary roles of a group
+    pub fn get_grants(group_identifier: Principal) -> Vec<RoleGrant> {
+        let prefix = format!("{}:", group_identifier);
+        ROLE_GRANTS.with(|grants| {
+            grants
+                .borrow()
+                .range(prefix.clone()..)
+                .take_while(|(key, _)| key.starts_with(&prefix))
+                .map(|(_, grant)| grant)
+                .collect()
+        })
+    }
+
+    // Method to remove all grants of a group, used when the group is permanently removed
+    pub fn remove_grants(group_identifier: Principal) {
+        for grant in Self::get_grants(group_identifier) {
+            Self::remove_grant(group_identifier, grant.member_identifier, &grant.role_name);
+        }
+    }
+
+    // Method to move the grants of a role to an other role, used when the role is renamed or removed
+    // the grants keep their expiry, without a new role the grants are removed
+    // if the member already has a grant for the new role the latest expiry is kept
+    pub fn move_grants(group_identifier: Principal, role_name: &str, new_role_name: Option<&str>) {
+        let grants: Vec<RoleGrant> = Self::get_grants(group_identifier)
+            .into_iter()
+            .filter(|grant| grant.role_name == role_name)
+            .collect();
+
+        for mut grant in grants {
+            Self::remove_grant(group_identifier, grant.member_identifier, &grant.role_name);
+
+            if let Some(_new_role_name) = new_role_name {
+                let existing = ROLE_GRANTS.with(|grants| {
+                    grants.borrow().get(&Self::get_key(
+                        group_identifier,
+                        grant.member_identifier,
+                        _new_role_name,
+                    ))
+                });
+                if let Some(_existing) = existing {
+                    grant.expires_on = grant.expires_on.max(_existing.expires_on);
+                }
+                grant.role_name = _new_role_name.to_string();
+                Self::add_grant(grant);
+            }
+        }
+    }
+
+    // Method to remove the roles of a member that are expired but not revoked yet
+    pub fn remove_expired_roles(
+        group_identifier: Principal,
+        member_identifier: Principal,
+        roles: Vec<String>,
+    ) -> Vec<String> {
+        roles
+            .into_iter()
+            .filter(|role_name| {
+                ROLE_GRANTS.with(|grants| {
+                    grants
+                        .borrow()
+                        .get(&Self::get_key(
+                            group_identifier,
+                            member_identifier,
+                            role_name,
+                        ))
+                        .is_none_or(|grant| grant.expires_on > time())
+                })
+            })
+            .collect()
+    }
+
+    // Method to revoke the expired grants, the member canisters are called through jobs to take the roles from the members
+    // returns the number of revoked grants
+    pub fn revoke_expired_grants() -> usize {
+        let expired_grants: Vec<(String, RoleGrant)> = ROLE_GRANTS.with(|grants| {
+            grants
+                .borrow()
+                .iter()
+                .filter(|(_, grant)| grant.expires_on <= time())
+                .take(REVOKE_BATCH_SIZE)
+                .collect()
+        });
+
+        for (key, grant) in &expired_grants {
+            ROLE_GRANTS.with(|grants| grants.borrow_mut().remove(key));
+            Jobs::add_jobs(
+                grant.group_identifier,
+                GroupJobKind::RoleRevoked {
+                    member_identifier: grant.member_identifier,
+                    role_name: grant.role_name.clone(),
+                },
+                vec![Self::get_member_canister(grant.member_identifier)],
+            );
+        }
+
+        expired_grants.len()
+    }
+
+    // The member canister is part of the member identifier
+    pub fn get_member_canister(member_identifier: Principal) -> Principal {
+        let (_, member_canister, _) = Identifier::decode(&member_identifier);
+        member_canister
+    }
+
+    fn get_key(
+        group_identifier: Principal,
+        member_identifier: Principal,
+        role_name: &str,
+    ) -> String {
+        format!("{}:{}:{}", group_identifier, member_identifier, role_name)
+    }
+}
+
+// Checks that the grants follow a renamed role and move to the fallback role when the role is removed
+#[test]
+pub fn move_grants() {
+    let group_identifier = Principal::from_slice(&[1]);
+    let member_identifier = Principal::from_slice(&[2]);
+    let other_member_identifier = Principal::from_slice(&[3]);
+    let grant = |member_identifier: Principal, role_name: &str, expires_on: u64| RoleGrant {
+        group_identifier,
+        member_identifier,
+        role_name: role_name.to_string(),
+        granted_by: Principal::anonymous(),
+        expires_on,
+        created_on: 0,
+    };
+    let get_roles = || {
+        let mut roles: Vec<(Principal, String, u64)> = RoleGrants::get_grants(group_identifier)
+            .into_iter()
+            .map(|grant| (grant.member_identifier, grant.role_name, grant.expires_on))
+            .collect();
+        roles.sort();
+        roles
+    };
+
+    RoleGrants::add_grant(grant(member_identifier, "helper", 10));
+    RoleGrants::add_grant(grant(other_member_identifier, "helper", 20));
+    RoleGrants::add_grant(grant(other_member_identifier, "reviewer", 30));
+
+    // renamed, both grants keep their expiry
+    RoleGrants::move_grants(group_identifier, "helper", Some("assistant"));
+    assert_eq!(
+        get_roles(),
+        vec![
+            (member_identifier, "assistant".to_string(), 10),
+            (other_member_identifier, "assistant".to_string(), 20),
+            (other_member_identifier, "reviewer".to_string(), 30),
+        ]
+    );
+
+    // removed with a fallback, the member that already had a grant for the fallback keeps the latest expiry
+    RoleGrants::move_grants(group_identifier, "assistant", Some("reviewer"));
+    assert_eq!(
+        get_roles(),
+        vec![
+            (member_identifier, "reviewer".to_string(), 10),
+            (other_member_identifier, "reviewer".to_string(), 30),
+        ]
+    );
+
+    // removed without a fallback
+    RoleGrants::move_grants(group_identifier, "reviewer", None);
+    assert!(get_roles().is_empty());
+}
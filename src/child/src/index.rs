@@ -0,0 +1,194 @@
+use std::collections::BTreeSet;
+
+use candid::Principal;
+
+use ic_scalable_canister::ic_scalable_misc::models::date_models::DateRange;
+use ic_stable_structures::StableBTreeMap;
+use shared::group_model::{Group, GroupFilter, GroupFilterExpression};
+
+use crate::store::{
+    CREATED_ON_INDEX, CREATED_ON_INDEX_MEMORY_ID, DELETED_INDEX, DELETED_INDEX_MEMORY_ID,
+    MEMORY_MANAGER, OWNER_INDEX, OWNER_INDEX_MEMORY_ID, TAG_INDEX, TAG_INDEX_MEMORY_ID,
+};
+
+// Secondary indexes on the tags, owner and creation date of the groups
+// the keys are `{value}:{group_identifier}`, numbers are zero padded so the keys are ordered by value
+// only groups that are not deleted are indexed, the deleted groups are kept in a separate index by owner
+pub struct GroupIndex;
+
+impl GroupIndex {
+    // Method to add a group to the indexes
+    pub fn add_group(identifier: &str, group: &Group) {
+        if group.is_deleted {
+            DELETED_INDEX.with(|index| {
+                index
+                    .borrow_mut()
+                    .insert(format!("{}:{}", group.owner, identifier), ())
+            });
+            return;
+        }
+
+        TAG_INDEX.with(|index| {
+            let mut index = index.borrow_mut();
+            for tag in &group.tags {
+                index.insert(Self::get_tag_key(*tag, identifier), ());
+            }
+        });
+        OWNER_INDEX.with(|index| {
+            index
+                .borrow_mut()
+                .insert(format!("{}:{}", group.owner, identifier), ())
+        });
+        CREATED_ON_INDEX.with(|index| {
+            index
+                .borrow_mut()
+                .insert(Self::get_created_on_key(group.created_on, identifier), ())
+        });
+    }
+
+    // Method to remove a group from the indexes
+    pub fn remove_group(identifier: &str, group: &Group) {
+        TAG_INDEX.with(|index| {
+            let mut index = index.borrow_mut();
+            for tag in &group.tags {
+                index.remove(&Self::get_tag_key(*tag, identifier));
+            }
+        });
+        OWNER_INDEX.with(|index| {
+            index
+                .borrow_mut()
+                .remove(&format!("{}:{}", group.owner, identifier))
+        });
+        CREATED_ON_INDEX.with(|index| {
+            index
+                .borrow_mut()
+                .remove(&Self::get_created_on_key(group.created_on, identifier))
+        });
+        DELETED_INDEX.with(|index| {
+            index
+                .borrow_mut()
+                .remove(&format!("{}:{}", group.owner, identifier))
+        });
+    }
+
+    // Method to rebuild the indexes from scratch
+    pub fn rebuild(groups: &[(String, Group)]) {
+        TAG_INDEX.with(|index| {
+            index.replace(StableBTreeMap::new(
+                MEMORY_MANAGER.with(|m| m.borrow().get(TAG_INDEX_MEMORY_ID)),
+            ))
+        });
+        OWNER_INDEX.with(|index| {
+            index.replace(StableBTreeMap::new(
+                MEMORY_MANAGER.with(|m| m.borrow().get(OWNER_INDEX_MEMORY_ID)),
+            ))
+        });
+        CREATED_ON_INDEX.with(|index| {
+            index.replace(StableBTreeMap::new(
+                MEMORY_MANAGER.with(|m| m.borrow().get(CREATED_ON_INDEX_MEMORY_ID)),
+            ))
+        });
+        DELETED_INDEX.with(|index| {
+            index.replace(StableBTreeMap::new(
+                MEMORY_MANAGER.with(|m| m.borrow().get(DELETED_INDEX_MEMORY_ID)),
+            ))
+        });
+
+        for (identifier, group) in groups {
+            Self::add_group(identifier, group);
+        }
+    }
+
+    // Method to get the identifiers of the deleted groups of an owner, or of all deleted groups if no owner is passed
+    pub fn get_deleted_identifiers(owner: Option<Principal>) -> BTreeSet<String> {
+        DELETED_INDEX.with(|index| match owner {
+            Some(_owner) => {
+                let prefix = format!("{}:", _owner);
+                Self::get_prefixed_identifiers(index.borrow().range(prefix.clone()..), &prefix)
+            }
+            None => index
+                .borrow()
+                .iter()
+                .filter_map(|(key, _)| {
+                    key.split_once(':')
+                        .map(|(_, identifier)| identifier.to_string())
+                })
+                .collect(),
+        })
+    }
+
+    // Method to get the identifiers of the groups that can match the filter expression
+    // returns `None` if the expression can't be answered by the indexes and all groups need to be checked
+    pub fn get_candidates(expression: &GroupFilterExpression) -> Option<BTreeSet<String>> {
+        match expression {
+            GroupFilterExpression::Filter(filter) => Self::get_identifiers(filter),
+            // A group needs to be in every indexed expression, the other expressions are checked afterwards
+            GroupFilterExpression::And(expressions) => expressions
+                .iter()
+                .filter_map(Self::get_candidates)
+                .reduce(|candidates, identifiers| {
+                    candidates.intersection(&identifiers).cloned().collect()
+                }),
+            // A group needs to be in any of the expressions, so every expression needs to be indexed
+            GroupFilterExpression::Or(expressions) => {
+                let mut candidates = BTreeSet::new();
+                for expression in expressions {
+                    candidates.append(&mut Self::get_candidates(expression)?);
+                }
+                Some(candidates)
+            }
+            GroupFilterExpression::Not(_) => None,
+        }
+    }
+
+    // Method to get the identifiers of the groups that match a filter, `None` if the filter is not indexed
+    fn get_identifiers(filter: &GroupFilter) -> Option<BTreeSet<String>> {
+        match filter {
+            GroupFilter::Tag(value) => Some(TAG_INDEX.with(|index| {
+                let prefix = format!("{:010}:", value);
+                Self::get_prefixed_identifiers(index.borrow().range(prefix.clone()..), &prefix)
+            })),
+            GroupFilter::Owner(value) => Some(OWNER_INDEX.with(|index| {
+                let prefix = format!("{}:", value);
+                Self::get_prefixed_identifiers(index.borrow().range(prefix.clone()..), &prefix)
+            })),
+            GroupFilter::CreatedOn(value) => Some(Self::get_created_on_identifiers(value)),
+            _ => None,
+        }
+    }
+
+    fn get_prefixed_identifiers(
+        keys: impl Iterator<Item = (String, ())>,
+        prefix: &str,
+    ) -> BTreeSet<String> {
+        keys.take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(key, _)| key[prefix.len()..].to_string())
+            .collect()
+    }
+
+    // An `end_date` of 0 means there is no end date, same as the `CreatedOn` filter
+    fn get_created_on_identifiers(date_range: &DateRange) -> BTreeSet<String> {
+        CREATED_ON_INDEX.with(|index| {
+            index
+                .borrow()
+                .range(format!("{:020}:", date_range.start_date)..)
+                .map_while(|(key, _)| {
+                    let (created_on, identifier) = key.split_once(':')?;
+                    let created_on = created_on.parse::<u64>().ok()?;
+                    if date_range.end_date > 0 && created_on > date_range.end_date {
+                        return None;
+                    }
+                    Some(identifier.to_string())
+                })
+                .collect()
+        })
+    }
+
+    fn get_tag_key(tag: u32, identifier: &str) -> String {
+        format!("{:010}:{}", tag, identifier)
+    }
+
+    fn get_created_on_key(created_on: u64, identifier: &str) -> String {
+        format!("{:020}:{}", created_on, identifier)
+    }
+}
@@ -0,0 +1,224 @@
+use candid::Principal;
+use hmac::{Hmac, Mac};
+use ic_cdk::api::{management_canister::main::raw_rand, time};
+use ic_stable_structures::StableBTreeMap;
+use sha2::Sha256;
+use shared::group_model::{GroupInvite, JoinGrant};
+
+use crate::store::{INVITES, INVITE_INDEX, INVITE_INDEX_MEMORY_ID, INVITE_SECRET, MEMORY_MANAGER};
+
+// Number of random bytes of an invite code, the code is the hex encoding of these bytes
+static INVITE_CODE_BYTES: usize = 8;
+
+// A join grant needs to be accepted by the member canister within this period
+static JOIN_GRANT_TTL_SECONDS: u64 = 10 * 60;
+
+// Invite codes of the groups, keyed by code
+// the codes of a group are indexed by `{group_identifier}:{code}` so the invites of a group can be found without a full scan
+// redeeming a code returns a join grant that is signed with a secret of this canister, so only this canister can issue and verify grants
+pub struct GroupInvites;
+
+impl GroupInvites {
+    // Method to generate a random invite code that is not in use yet
+    pub async fn generate_code() -> Result<String, String> {
+        loop {
+            let (bytes,) = raw_rand().await.map_err(|(_, err)| err)?;
+            let code = hex::encode(&bytes[..INVITE_CODE_BYTES]);
+            if Self::get_invite(&code).is_none() {
+                return Ok(code);
+            }
+        }
+    }
+
+    pub fn add_invite(invite: GroupInvite) {
+        INVITE_INDEX.with(|index| {
+            index.borrow_mut().insert(
+                Self::get_index_key(invite.group_identifier, &invite.code),
+                (),
+            )
+        });
+        INVITES.with(|invites| invites.borrow_mut().insert(invite.code.clone(), invite));
+    }
+
+    pub fn get_invite(code: &str) -> Option<GroupInvite> {
+        INVITES.with(|invites| invites.borrow().get(&code.to_string()))
+    }
+
+    pub fn remove_invite(code: &str) {
+        if let Some(_invite) =
+            INVITES.with(|invites| invites.borrow_mut().remove(&code.to_string()))
+        {
+            INVITE_INDEX.with(|index| {
+                index
+                    .borrow_mut()
+                    .remove(&Self::get_index_key(_invite.group_identifier, code))
+            });
+        }
+    }
+
+    // Method to get the invites of a group that can still be redeemed
+    pub fn get_active_invites(group_identifier: Principal) -> Vec<GroupInvite> {
+        Self::get_invites(group_identifier)
+            .into_iter()
+            .filter(Self::is_active)
+            .collect()
+    }
+
+    // Method to remove all invites of a group, used when the group is permanently removed
+    pub fn remove_invites(group_identifier: Principal) {
+        for invite in Self::get_invites(group_identifier) {
+            Self::remove_invite(&invite.code);
+        }
+    }
+
+    // Method to update the invites of a role, used when the role is renamed or removed
+    // the invites follow a renamed role, without a new role the invites are removed
+    pub fn move_invites(group_identifier: Principal, role_name: &str, new_role_name: Option<&str>) {
+        let invites: Vec<GroupInvite> = Self::get_invites(group_identifier)
+            .into_iter()
+            .filter(|invite| invite.role_name.as_deref() == Some(role_name))
+            .collect();
+
+        for mut invite in invites {
+            match new_role_name {
+                Some(_new_role_name) => {
+                    invite.role_name = Some(_new_role_name.to_string());
+                    Self::add_invite(invite);
+                }
+                None => Self::remove_invite(&invite.code),
+            }
+        }
+    }
+
+    // Method to rebuild the index of the invites, used as a migration for invites that were added before the index existed
+    pub fn rebuild_index() {
+        INVITE_INDEX.with(|index| {
+            index.replace(StableBTreeMap::new(
+                MEMORY_MANAGER.with(|m| m.borrow().get(INVITE_INDEX_MEMORY_ID)),
+            ))
+        });
+
+        let invites: Vec<GroupInvite> =
+            INVITES.with(|invites| invites.borrow().iter().map(|(_, invite)| invite).collect());
+        for invite in invites {
+            Self::add_invite(invite);
+        }
+    }
+
+    // An invite is active if it is not expired and not used up
+    pub fn is_active(invite: &GroupInvite) -> bool {
+        invite
+            .expires_on
+            .is_none_or(|expires_on| expires_on > time())
+            && invite
+                .max_uses
+                .is_none_or(|max_uses| invite.uses < max_uses)
+    }
+
+    // Method to count a use of the invite and issue a signed join grant for the principal
+    // the secret needs to be fetched with `get_secret` first, so there is no await between checking and using the invite
+    pub fn issue_join_grant(
+        secret: &[u8],
+        mut invite: GroupInvite,
+        principal: Principal,
+    ) -> JoinGrant {
+        invite.uses += 1;
+        let mut grant = JoinGrant {
+            group_identifier: invite.group_identifier,
+            principal,
+            role_name: invite
+                .role_name
+                .clone()
+                .unwrap_or_else(|| "member".to_string()),
+            invite_code: invite.code.clone(),
+            issued_on: time(),
+            expires_on: time() + JOIN_GRANT_TTL_SECONDS * 1_000_000_000,
+            signature: String::new(),
+        };
+        grant.signature = Self::get_signature(secret, &grant);
+
+        Self::add_invite(invite);
+        grant
+    }
+
+    // Method to check if the join grant is issued by this canister, not expired and the invite is not revoked
+    pub fn verify_join_grant(grant: &JoinGrant) -> bool {
+        let secret = INVITE_SECRET.with(|secret| secret.borrow().get().clone());
+        if secret.is_empty()
+            || grant.expires_on <= time()
+            || Self::get_invite(&grant.invite_code)
+                .is_none_or(|invite| invite.group_identifier != grant.group_identifier)
+        {
+            return false;
+        }
+
+        // `verify_slice` compares in constant time
+        match hex::decode(&grant.signature) {
+            Ok(_signature) => Self::get_mac(&secret, grant)
+                .verify_slice(&_signature)
+                .is_ok(),
+            Err(_) => false,
+        }
+    }
+
+    fn get_invites(group_identifier: Principal) -> Vec<GroupInvite> {
+        let prefix = format!("{}:", group_identifier);
+        let codes: Vec<String> = INVITE_INDEX.with(|index| {
+            index
+                .borrow()
+                .range(prefix.clone()..)
+                .take_while(|(key, _)| key.starts_with(&prefix))
+                .map(|(key, _)| key[prefix.len()..].to_string())
+                .collect()
+        });
+
+        codes
+            .iter()
+            .filter_map(|code| Self::get_invite(code))
+            .collect()
+    }
+
+    fn get_index_key(group_identifier: Principal, code: &str) -> String {
+        format!("{}:{}", group_identifier, code)
+    }
+
+    // The signature is the HMAC-SHA256 of the fields of the grant with the secret as key, the role name is last because it can contain `:`
+    fn get_signature(secret: &[u8], grant: &JoinGrant) -> String {
+        hex::encode(Self::get_mac(secret, grant).finalize().into_bytes())
+    }
+
+    fn get_mac(secret: &[u8], grant: &JoinGrant) -> Hmac<Sha256> {
+        let message = format!(
+            "{}:{}:{}:{}:{}:{}",
+            grant.group_identifier,
+            grant.principal,
+            grant.invite_code,
+            grant.issued_on,
+            grant.expires_on,
+            grant.role_name
+        );
+        // HMAC accepts keys of any length
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts any key length");
+        mac.update(message.as_bytes());
+        mac
+    }
+
+    // The secret is generated on first use and kept in stable memory so the grants stay valid after an upgrade
+    pub async fn get_secret() -> Result<Vec<u8>, String> {
+        let secret = INVITE_SECRET.with(|secret| secret.borrow().get().clone());
+        if !secret.is_empty() {
+            return Ok(secret);
+        }
+
+        let (bytes,) = raw_rand().await.map_err(|(_, err)| err)?;
+
+        // another call could have set the secret while waiting on `raw_rand`
+        INVITE_SECRET.with(|secret| {
+            let mut secret = secret.borrow_mut();
+            if secret.get().is_empty() {
+                let _ = secret.set(bytes);
+            }
+            Ok(secret.get().clone())
+        })
+    }
+}
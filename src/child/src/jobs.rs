@@ -0,0 +1,277 @@
+use std::{cell::Cell, collections::HashSet};
+
+use candid::Principal;
+use ic_cdk::api::{call, time};
+use ic_scalable_canister::ic_scalable_misc::enums::api_error_type::ApiError;
+use shared::group_model::{GroupJob, GroupJobKind, GroupJobStatus};
+
+use crate::{
+    role_cache::MemberRoleCache,
+    store::{DEPENDENT_CANISTERS, JOBS},
+};
+
+// Max number of calls per job before it is marked as failed
+static MAX_JOB_ATTEMPTS: u32 = 10;
+
+// Max number of jobs that are run in a single run
+static JOB_BATCH_SIZE: usize = 50;
+
+// Finished jobs are kept for this long so their status can be checked
+static FINISHED_JOB_RETENTION_SECONDS: u64 = 30 * 24 * 60 * 60;
+
+thread_local! {
+    // Prevents a timer from starting a run while the previous run is still waiting on calls
+    static IS_RUNNING: Cell<bool> = const { Cell::new(false) };
+}
+
+// Marks a run as started, the mark is cleared when the guard is dropped
+// the guard is also dropped when the run traps after a call, so the next run can still start
+struct RunGuard;
+
+impl RunGuard {
+    fn start() -> Option<RunGuard> {
+        match IS_RUNNING.with(|running| running.replace(true)) {
+            true => None,
+            false => Some(RunGuard),
+        }
+    }
+}
+
+impl Drop for RunGuard {
+    fn drop(&mut self) {
+        IS_RUNNING.with(|running| running.set(false));
+    }
+}
+
+// Queue of inter-canister calls that need to succeed, every call is tracked as a job in stable memory
+// pending jobs are run right after they are added and retried on a timer until they succeed or run out of attempts
+pub struct Jobs;
+
+impl Jobs {
+    // Method to add a job per canister, the jobs are started right away
+    pub fn add_jobs(group_identifier: Principal, kind: GroupJobKind, canisters: Vec<Principal>) {
+        JOBS.with(|jobs| {
+            let mut jobs = jobs.borrow_mut();
+            for canister in canisters {
+                let id = jobs.last_key_value().map(|(id, _)| id + 1).unwrap_or(0);
+                jobs.insert(
+                    id,
+                    GroupJob {
+                        group_identifier,
+                        canister,
+                        kind: kind.clone(),
+                        status: GroupJobStatus::Pending,
+                        attempts: 0,
+                        last_error: None,
+                        created_on: time(),
+                        updated_on: time(),
+                    },
+                );
+            }
+        });
+
+        ic_cdk::spawn(Self::run_jobs());
+    }
+
+    // Method to run the pending jobs, the result of every call is stored on the job
+    // the jobs of a group for a canister are run in the order they are added, a pending job blocks the later jobs of the same canister
+    // so a retried call can't undo a later call (for example a rename that is retried after the renamed role is removed)
+    pub async fn run_jobs() {
+        let Some(_guard) = RunGuard::start() else {
+            return;
+        };
+
+        // Queues with a failed call wait for the next run
+        let mut blocked_queues: HashSet<(Principal, Principal)> = HashSet::new();
+
+        for _ in 0..JOB_BATCH_SIZE {
+            // The first pending job of a queue that is not blocked is the oldest job of that queue
+            let next_job = JOBS.with(|jobs| {
+                jobs.borrow().iter().find(|(_, job)| {
+                    job.status == GroupJobStatus::Pending
+                        && !blocked_queues.contains(&(job.group_identifier, job.canister))
+                })
+            });
+
+            let Some((id, mut job)) = next_job else {
+                break;
+            };
+
+            let result = Self::run_job(&job).await;
+
+            job.attempts += 1;
+            job.updated_on = time();
+            match result {
+                Ok(_) => {
+                    job.status = GroupJobStatus::Done;
+                    job.last_error = None;
+                    Self::invalidate_member_roles(&job);
+                }
+                Err(err) => {
+                    if job.attempts >= MAX_JOB_ATTEMPTS {
+                        job.status = GroupJobStatus::Failed;
+                    } else {
+                        blocked_queues.insert((job.group_identifier, job.canister));
+                    }
+                    job.last_error = Some(err);
+                }
+            }
+
+            JOBS.with(|jobs| jobs.borrow_mut().insert(id, job));
+        }
+
+        Self::remove_finished_jobs();
+    }
+
+    // Method to get all the jobs of a group
+    pub fn get_group_jobs(group_identifier: Principal) -> Vec<(u64, GroupJob)> {
+        JOBS.with(|jobs| {
+            jobs.borrow()
+                .iter()
+                .filter(|(_, job)| job.group_identifier == group_identifier)
+                .collect()
+        })
+    }
+
+    // Method to remove all jobs of a group, used when the group is permanently removed
+    pub fn remove_group_jobs(group_identifier: Principal) {
+        let ids: Vec<u64> = Self::get_group_jobs(group_identifier)
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect();
+
+        JOBS.with(|jobs| {
+            let mut jobs = jobs.borrow_mut();
+            for id in ids {
+                jobs.remove(&id);
+            }
+        });
+    }
+
+    // Method to get the canisters that need to know when a group is deleted or restored (for example event canisters)
+    pub fn get_dependent_canisters() -> Vec<Principal> {
+        DEPENDENT_CANISTERS.with(|canisters| {
+            canisters
+                .borrow()
+                .iter()
+                .filter_map(|(canister, _)| Principal::from_text(canister).ok())
+                .collect()
+        })
+    }
+
+    pub fn add_dependent_canister(canister: Principal) {
+        DEPENDENT_CANISTERS
+            .with(|canisters| canisters.borrow_mut().insert(canister.to_string(), ()));
+    }
+
+    pub fn remove_dependent_canister(canister: Principal) {
+        DEPENDENT_CANISTERS.with(|canisters| canisters.borrow_mut().remove(&canister.to_string()));
+    }
+
+    // Inter canister call that belongs to the job kind
+    async fn run_job(job: &GroupJob) -> Result<(), String> {
+        let result: Result<(Result<(), ApiError>,), _> = match &job.kind {
+            GroupJobKind::GroupDeleted => {
+                call::call(job.canister, "group_deleted", (job.group_identifier,)).await
+            }
+            GroupJobKind::GroupSoftDeleted => {
+                call::call(job.canister, "group_soft_deleted", (job.group_identifier,)).await
+            }
+            GroupJobKind::GroupRestored => {
+                call::call(job.canister, "group_restored", (job.group_identifier,)).await
+            }
+            GroupJobKind::RoleRenamed { old_name, new_name } => {
+                call::call(
+                    job.canister,
+                    "rename_group_role",
+                    (job.group_identifier, old_name, new_name),
+                )
+                .await
+            }
+            GroupJobKind::RoleRemoved {
+                role_name,
+                fallback_role,
+            } => {
+                call::call(
+                    job.canister,
+                    "remove_group_role",
+                    (job.group_identifier, role_name, fallback_role),
+                )
+                .await
+            }
+            GroupJobKind::RoleAssigned {
+                member_identifier,
+                role_name,
+            } => {
+                call::call(
+                    job.canister,
+                    "assign_group_role",
+                    (job.group_identifier, member_identifier, role_name),
+                )
+                .await
+            }
+            GroupJobKind::RoleRevoked {
+                member_identifier,
+                role_name,
+            } => {
+                call::call(
+                    job.canister,
+                    "revoke_group_role",
+                    (job.group_identifier, member_identifier, role_name),
+                )
+                .await
+            }
+            GroupJobKind::OwnershipTransferred {
+                previous_owner,
+                new_owner,
+            } => {
+                call::call(
+                    job.canister,
+                    "transfer_group_ownership",
+                    (job.group_identifier, previous_owner, new_owner),
+                )
+                .await
+            }
+        };
+
+        match result {
+            Ok((Ok(_),)) => Ok(()),
+            Ok((Err(err),)) => Err(format!("{:?}", err)),
+            Err((code, message)) => Err(format!("{:?} - {}", code, message)),
+        }
+    }
+
+    // The roles of the members changed after a successful call, so the cached roles are outdated
+    fn invalidate_member_roles(job: &GroupJob) {
+        match &job.kind {
+            GroupJobKind::RoleAssigned {
+                member_identifier, ..
+            }
+            | GroupJobKind::RoleRevoked {
+                member_identifier, ..
+            } => MemberRoleCache::invalidate(*member_identifier, job.group_identifier),
+            _ => MemberRoleCache::invalidate_group(job.group_identifier),
+        }
+    }
+
+    // Method to remove the jobs that are finished longer than the retention period
+    fn remove_finished_jobs() {
+        let finished_jobs: Vec<u64> = JOBS.with(|jobs| {
+            jobs.borrow()
+                .iter()
+                .filter(|(_, job)| {
+                    job.status != GroupJobStatus::Pending
+                        && job.updated_on + FINISHED_JOB_RETENTION_SECONDS * 1_000_000_000 < time()
+                })
+                .map(|(id, _)| id)
+                .collect()
+        });
+
+        JOBS.with(|jobs| {
+            let mut jobs = jobs.borrow_mut();
+            for id in finished_jobs {
+                jobs.remove(&id);
+            }
+        });
+    }
+}
@@ -1,8 +1,17 @@
 pub static IDENTIFIER_KIND: &str = "grp";
 
+pub mod audit;
 pub mod backup;
 pub mod default;
+pub mod grants;
+pub mod index;
+pub mod invites;
+pub mod jobs;
 pub mod methods;
+pub mod role_cache;
+pub mod roles;
+pub mod search;
 mod stable_backup;
 pub mod store;
 pub mod validation;
+pub mod versions;
@@ -8,7 +8,13 @@ use ic_scalable_canister::ic_scalable_misc::{
         permissions_models::PostPermission,
     },
 };
-use shared::group_model::{Group, GroupFilter, GroupResponse, GroupSort, PostGroup, UpdateGroup};
+use shared::group_model::{
+    DeletionPolicy, Group, GroupAuditRecord, GroupChunkQuery, GroupFilter, GroupFilterExpression,
+    GroupInvite, GroupJob, GroupResponse, GroupSort, GroupVersion, JoinEligibility, JoinGrant,
+    PatchGroup, PostGroup, RoleGrant, UpdateGroup,
+};
+
+use crate::{backup::is_owner, jobs::Jobs};
 
 use super::store::{Store, STABLE_DATA};
 
@@ -30,6 +36,7 @@ fn get_group(identifier: Principal) -> Result<GroupResponse, ApiError> {
 }
 
 // This method is used to get groups filtered and sorted with pagination
+// the optional filter expression allows nested and / or / not filters, groups need to match both the filters and the expression
 #[query]
 fn get_groups(
     limit: usize,
@@ -38,6 +45,7 @@ fn get_groups(
     filter_type: FilterType,
     sort: GroupSort,
     include_invite_only: bool,
+    filter_expression: Option<GroupFilterExpression>,
 ) -> Result<PagedResponse<GroupResponse>, ApiError> {
     Ok(Store::get_groups(
         limit,
@@ -46,18 +54,73 @@ fn get_groups(
         filter_type,
         sort,
         include_invite_only,
+        filter_expression,
     ))
 }
 
 // This method is used to edit a group
+// the member needs the edit permission for every part of the profile that changes (`group_profile`, `group_image` and `group_privacy`)
 #[update(guard = "auth")]
 async fn edit_group(
     group_identifier: Principal,
     update_group: UpdateGroup,
     member_identifier: Principal,
 ) -> Result<GroupResponse, ApiError> {
+    match Store::can_edit_profile(caller(), group_identifier, member_identifier, &update_group)
+        .await
+    {
+        Ok(_editor) => Store::update_group(_editor, group_identifier, update_group),
+        Err(err) => Err(err),
+    }
+}
+
+// This method is used to edit only the passed fields of a group
+// pass `expected_updated_on` (the `updated_on` of the group when it was read) to reject the edit if someone else changed the group in the meantime
+#[update(guard = "auth")]
+async fn patch_group(
+    group_identifier: Principal,
+    patch: PatchGroup,
+    member_identifier: Principal,
+) -> Result<GroupResponse, ApiError> {
+    let update_group = Store::get_group_patched_profile(group_identifier, patch.clone())?;
+    match Store::can_edit_profile(caller(), group_identifier, member_identifier, &update_group)
+        .await
+    {
+        Ok(_editor) => Store::patch_group(_editor, group_identifier, patch),
+        Err(err) => Err(err),
+    }
+}
+
+// This method is used to get the earlier versions of the profile of a group, newest first
+// this and the other methods that check the roles of the caller are update calls, the permission check of a query is not certified and the fetched roles would not be cached
+#[update(guard = "auth")]
+async fn get_group_versions(
+    group_identifier: Principal,
+    member_identifier: Principal,
+) -> Result<Vec<GroupVersion>, ApiError> {
     match Store::can_edit(caller(), group_identifier, member_identifier).await {
-        Ok(_caller) => Store::update_group(_caller, group_identifier, update_group),
+        Ok(_) => Ok(Store::get_group_versions(group_identifier)),
+        Err(err) => Err(err),
+    }
+}
+
+// This method is used to set the profile of a group back to an earlier version
+#[update(guard = "auth")]
+async fn revert_group_to_version(
+    group_identifier: Principal,
+    version: u64,
+    member_identifier: Principal,
+) -> Result<GroupResponse, ApiError> {
+    let _version = Store::get_group_version(group_identifier, version)?;
+    match Store::can_edit_profile(
+        caller(),
+        group_identifier,
+        member_identifier,
+        &_version.profile,
+    )
+    .await
+    {
+        Ok(_editor) => Store::revert_group_to_version(_editor, group_identifier, version),
         Err(err) => Err(err),
     }
 }
@@ -66,18 +129,28 @@ async fn edit_group(
 // This methods is used by the parent canister to get filtered groups the (this) child canister
 // Data serialized and send as byte array chunks ` (bytes, (start_chunk, end_chunk)) `
 // The parent canister can then deserialize the data and pass it to the frontend
+// When a query is passed only the sorted groups after the query cursor are returned (keyset pagination)
 #[query]
 fn get_chunked_data(
     filters: Vec<GroupFilter>,
     filter_type: FilterType,
     chunk: usize,
     max_bytes_per_chunk: usize,
+    query: Option<GroupChunkQuery>,
+    filter_expression: Option<GroupFilterExpression>,
 ) -> (Vec<u8>, (usize, usize)) {
     if STABLE_DATA.with(|data| data.borrow().get().parent != caller()) {
         return (vec![], (0, 0));
     }
 
-    Store::get_chunked_data(filters, filter_type, chunk, max_bytes_per_chunk)
+    Store::get_chunked_data(
+        filters,
+        filter_type,
+        chunk,
+        max_bytes_per_chunk,
+        query,
+        filter_expression,
+    )
 }
 
 // This method is used to get the owner and privacy of a group
@@ -96,32 +169,155 @@ fn get_groups_by_id(group_identifiers: Vec<Principal>) -> Result<Vec<GroupRespon
 }
 
 // This method is used to (soft) delete a group
+// the owner can always delete the group, other members need the delete permission for `group_delete`
 #[update(guard = "auth")]
-fn delete_group(
+async fn delete_group(
     group_identifier: Principal,
-    _member_identifier: Principal,
+    member_identifier: Principal,
 ) -> Result<Group, ApiError> {
-    Store::delete_group(caller(), group_identifier)
+    match Store::can_delete(caller(), group_identifier, member_identifier).await {
+        Ok(_caller) => Store::delete_group(_caller, group_identifier),
+        Err(err) => Err(err),
+    }
+}
+
+// This method is used to restore a (soft) deleted group, only the owner can restore the group within the restore period
+#[update(guard = "auth")]
+fn restore_group(group_identifier: Principal) -> Result<GroupResponse, ApiError> {
+    Store::restore_group(caller(), group_identifier)
+}
+
+// This method is used to get the (soft) deleted groups that can still be restored
+// returns the groups owned by the caller, and the groups the member has the delete permission for (`group_delete`) if a member identifier is passed
+#[update(guard = "auth")]
+async fn get_deleted_groups(member_identifier: Option<Principal>) -> Vec<GroupResponse> {
+    Store::get_deleted_groups(caller(), member_identifier).await
+}
+
+// This method is used to get the changes made to a group, newest first
+// moderators and admins need to pass their member identifier, the owner can leave it empty
+#[update(guard = "auth")]
+async fn get_group_audit_log(
+    group_identifier: Principal,
+    member_identifier: Option<Principal>,
+    limit: usize,
+    page: usize,
+) -> Result<PagedResponse<GroupAuditRecord>, ApiError> {
+    Store::get_group_audit_log(caller(), group_identifier, member_identifier, limit, page).await
+}
+
+// This method is used to get how long deleted groups can be restored and when they are permanently removed
+#[query]
+fn get_deletion_policy() -> DeletionPolicy {
+    Store::get_deletion_policy()
+}
+
+// This method is used to set how long deleted groups can be restored and when they are permanently removed
+#[update(guard = "is_owner")]
+fn set_deletion_policy(policy: DeletionPolicy) -> Result<DeletionPolicy, ApiError> {
+    Store::set_deletion_policy(policy)
 }
 
+// This method is used to get the tracked inter-canister calls of a group, for example the cleanup after a group or role is removed
+#[query]
+fn get_group_jobs(group_identifier: Principal) -> Vec<(u64, GroupJob)> {
+    Jobs::get_group_jobs(group_identifier)
+}
+
+// This method is used to get the canisters that are notified when a group is deleted or restored
+#[query]
+fn get_dependent_canisters() -> Vec<Principal> {
+    Jobs::get_dependent_canisters()
+}
+
+// This method is used to register a canister (for example an event canister) that is notified when a group is deleted or restored
+// the canister needs to implement `group_soft_deleted(group_identifier: Principal) -> Result<(), ApiError>`,
+// `group_restored(group_identifier: Principal) -> Result<(), ApiError>` and `group_deleted(group_identifier: Principal) -> Result<(), ApiError>`
+#[update(guard = "is_owner")]
+fn add_dependent_canister(canister: Principal) {
+    Jobs::add_dependent_canister(canister)
+}
+
+#[update(guard = "is_owner")]
+fn remove_dependent_canister(canister: Principal) {
+    Jobs::remove_dependent_canister(canister)
+}
+
+// This method is used as a migration after an upgrade that adds or changes an index, the search and filter indexes are rebuilt from the stored groups
+#[update(guard = "is_owner")]
+fn rebuild_indexes() {
+    Store::rebuild_indexes()
+}
+
+// This method is used to transfer the ownership of a group to an existing member of the group
 #[update(guard = "auth")]
-pub fn add_wallet(
+async fn transfer_ownership(
+    group_identifier: Principal,
+    new_owner_member_identifier: Principal,
+) -> Result<GroupResponse, ApiError> {
+    Store::transfer_ownership(caller(), group_identifier, new_owner_member_identifier).await
+}
+
+// This method is used to propose a new owner for a group, the proposed member needs to accept the ownership
+// the proposal expires after `expires_in_seconds` (defaults to 7 days, max 30 days)
+#[update(guard = "auth")]
+async fn propose_ownership_transfer(
+    group_identifier: Principal,
+    new_owner_member_identifier: Principal,
+    expires_in_seconds: Option<u64>,
+) -> Result<GroupResponse, ApiError> {
+    Store::propose_ownership_transfer(
+        caller(),
+        group_identifier,
+        new_owner_member_identifier,
+        expires_in_seconds,
+    )
+    .await
+}
+
+// This method is used by the proposed owner to accept the ownership of a group
+#[update(guard = "auth")]
+async fn accept_ownership(group_identifier: Principal) -> Result<GroupResponse, ApiError> {
+    Store::accept_ownership(caller(), group_identifier).await
+}
+
+// This method is used by the proposed owner to decline the ownership of a group
+#[update(guard = "auth")]
+fn decline_ownership(group_identifier: Principal) -> Result<(), ApiError> {
+    Store::decline_ownership(caller(), group_identifier)
+}
+
+// This method is used to add a wallet to a group
+// the owner can leave the member identifier empty, other members need the edit permission for wallets
+#[update(guard = "auth")]
+pub async fn add_wallet(
     group_identifier: Principal,
     wallet_canister: Principal,
     description: String,
+    member_identifier: Option<Principal>,
 ) -> Result<(), ApiError> {
-    Store::add_wallet(caller(), group_identifier, wallet_canister, description)
+    match Store::can_manage_wallets(caller(), group_identifier, member_identifier).await {
+        Ok(_caller) => Store::add_wallet(_caller, group_identifier, wallet_canister, description),
+        Err(err) => Err(err),
+    }
 }
 
+// This method is used to remove a wallet from a group
+// the owner can leave the member identifier empty, other members need the edit permission for wallets
 #[update(guard = "auth")]
-pub fn remove_wallet(
+pub async fn remove_wallet(
     group_identifier: Principal,
     wallet_canister: Principal,
+    member_identifier: Option<Principal>,
 ) -> Result<(), ApiError> {
-    Store::remove_wallet(caller(), group_identifier, wallet_canister)
+    match Store::can_manage_wallets(caller(), group_identifier, member_identifier).await {
+        Ok(_caller) => Store::remove_wallet(_caller, group_identifier, wallet_canister),
+        Err(err) => Err(err),
+    }
 }
 
 // This method is used to add a custom role to a group
+// pass the `revision` of the group that was read to reject the change if the group changed in the meantime
 #[update(guard = "auth")]
 async fn add_role(
     group_identifier: Principal,
@@ -129,26 +325,208 @@ async fn add_role(
     color: String,
     index: u64,
     member_identifier: Principal,
+    expected_revision: Option<u64>,
 ) -> Result<GroupRole, ApiError> {
-    match Store::can_edit(caller(), group_identifier, member_identifier).await {
-        Ok(_caller) => Store::add_role(_caller, group_identifier, role_name, color, index),
+    match Store::can_manage_roles(caller(), group_identifier, member_identifier).await {
+        Ok(_caller) => Store::add_role(
+            _caller,
+            group_identifier,
+            role_name,
+            color,
+            index,
+            expected_revision,
+        ),
         Err(err) => Err(err),
     }
 }
 
 // This method is used to remove a custom role from a group
+// members with the role get the optional fallback role, the member canisters are updated in the background (see `get_group_jobs`)
+// pass the `revision` of the group that was read to reject the change if the group changed in the meantime
 #[update(guard = "auth")]
 async fn remove_role(
     group_identifier: Principal,
     role_name: String,
     member_identifier: Principal,
+    expected_revision: Option<u64>,
+    fallback_role: Option<String>,
 ) -> Result<bool, ApiError> {
-    match Store::can_edit(caller(), group_identifier, member_identifier).await {
-        Ok(_caller) => Store::remove_role(_caller, group_identifier, role_name),
+    match Store::can_manage_roles(caller(), group_identifier, member_identifier).await {
+        Ok(_caller) => Store::remove_role(
+            _caller,
+            group_identifier,
+            role_name,
+            fallback_role,
+            expected_revision,
+        ),
+        Err(err) => Err(err),
+    }
+}
+
+// This method is used to change the name, color and index of a custom role, the permissions of the role are kept
+// members keep a renamed role, the member canisters are notified of the new name
+#[update(guard = "auth")]
+async fn edit_role(
+    group_identifier: Principal,
+    role_name: String,
+    new_name: String,
+    color: String,
+    index: u64,
+    member_identifier: Principal,
+    expected_revision: Option<u64>,
+) -> Result<GroupRole, ApiError> {
+    match Store::can_manage_roles(caller(), group_identifier, member_identifier).await {
+        Ok(_caller) => Store::edit_role(
+            _caller,
+            group_identifier,
+            role_name,
+            new_name,
+            color,
+            index,
+            expected_revision,
+        ),
+        Err(err) => Err(err),
+    }
+}
+
+// This method is used to set the order of the custom roles, every custom role needs to be passed exactly once
+#[update(guard = "auth")]
+async fn reorder_roles(
+    group_identifier: Principal,
+    role_names: Vec<String>,
+    member_identifier: Principal,
+    expected_revision: Option<u64>,
+) -> Result<Vec<GroupRole>, ApiError> {
+    match Store::can_manage_roles(caller(), group_identifier, member_identifier).await {
+        Ok(_caller) => {
+            Store::reorder_roles(_caller, group_identifier, role_names, expected_revision)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+// This method is used to assign a role to a member, the role is revoked at `expires_on` (nanoseconds) if passed
+// the member canister of the assignee is updated in the background (see `get_group_jobs`)
+#[update(guard = "auth")]
+async fn assign_role(
+    group_identifier: Principal,
+    assignee_member_identifier: Principal,
+    role_name: String,
+    expires_on: Option<u64>,
+    member_identifier: Principal,
+) -> Result<(), ApiError> {
+    match Store::can_manage_roles(caller(), group_identifier, member_identifier).await {
+        Ok(_manager) => {
+            Store::assign_role(
+                _manager,
+                group_identifier,
+                assignee_member_identifier,
+                role_name,
+                expires_on,
+            )
+            .await
+        }
+        Err(err) => Err(err),
+    }
+}
+
+// This method is used to create an invite code for an invite only or private group
+// the code can be redeemed `max_uses` times until `expires_on` (nanoseconds) if passed, redeemers join with `role_name` or the member role
+#[update(guard = "auth")]
+async fn create_invite(
+    group_identifier: Principal,
+    role_name: Option<String>,
+    max_uses: Option<u64>,
+    expires_on: Option<u64>,
+    member_identifier: Principal,
+) -> Result<GroupInvite, ApiError> {
+    match Store::can_manage_invites(caller(), group_identifier, member_identifier).await {
+        Ok(_manager) => {
+            Store::create_invite(_manager, group_identifier, role_name, max_uses, expires_on).await
+        }
+        Err(err) => Err(err),
+    }
+}
+
+// This method is used to get the invites of a group that are not expired or used up
+#[update(guard = "auth")]
+async fn get_group_invites(
+    group_identifier: Principal,
+    member_identifier: Principal,
+) -> Result<Vec<GroupInvite>, ApiError> {
+    match Store::can_manage_invites(caller(), group_identifier, member_identifier).await {
+        Ok(_) => Ok(Store::get_group_invites(group_identifier)),
+        Err(err) => Err(err),
+    }
+}
+
+// This method is used to revoke an invite code
+#[update(guard = "auth")]
+async fn revoke_invite(
+    group_identifier: Principal,
+    code: String,
+    member_identifier: Principal,
+) -> Result<(), ApiError> {
+    match Store::can_manage_invites(caller(), group_identifier, member_identifier).await {
+        Ok(_caller) => Store::revoke_invite(_caller.principal, group_identifier, code),
         Err(err) => Err(err),
     }
 }
 
+// This method is used to redeem an invite code, the returned join grant is passed to the member canister to join the group
+#[update(guard = "auth")]
+async fn redeem_invite(code: String) -> Result<JoinGrant, ApiError> {
+    Store::redeem_invite(caller(), code).await
+}
+
+// This method is used by the member canister to check if a join grant is issued by this canister, not expired and the invite is not revoked
+#[query]
+fn verify_join_grant(join_grant: JoinGrant) -> bool {
+    Store::verify_join_grant(join_grant)
+}
+
+// This method is used to check which gates of a group the caller passes, with the balances and neurons that were checked
+// the `account_identifier` is needed for EXT canisters
+#[update(guard = "auth")]
+async fn check_join_eligibility(
+    group_identifier: Principal,
+    account_identifier: Option<String>,
+) -> Result<JoinEligibility, ApiError> {
+    Store::check_join_eligibility(caller(), group_identifier, account_identifier).await
+}
+
+// This method is used by the member canister to remove the cached roles of a member after its roles changed
+// the roles of members are cached for a few minutes to prevent a call to the member canister on every permission check
+#[update]
+fn invalidate_member_roles(
+    member_identifier: Principal,
+    group_identifier: Principal,
+) -> Result<(), ApiError> {
+    Store::invalidate_member_roles(caller(), member_identifier, group_identifier)
+}
+
+// This method is used to fetch the roles of a member from the member canister instead of using the cached roles
+#[update(guard = "auth")]
+async fn refresh_member_roles(
+    group_identifier: Principal,
+    member_identifier: Principal,
+) -> Result<Vec<String>, ApiError> {
+    Store::refresh_member_roles(caller(), group_identifier, member_identifier).await
+}
+
+// This method is used to get the temporary roles of a group that are not revoked yet
+#[query]
+fn get_role_grants(group_identifier: Principal) -> Vec<RoleGrant> {
+    Store::get_role_grants(group_identifier)
+}
+
+// This method is used to get the rank of every role of a group, highest role first
+// a member can only add, edit, assign or remove roles ranked below their own highest role
+#[query]
+fn get_role_ranks(group_identifier: Principal) -> Vec<(String, u64)> {
+    Store::get_role_ranks(group_identifier)
+}
+
 // This method is used to get all the roles of a group
 #[query]
 fn get_group_roles(group_identifier: Principal) -> Vec<GroupRole> {
@@ -156,17 +534,23 @@ fn get_group_roles(group_identifier: Principal) -> Vec<GroupRole> {
 }
 
 // This method is used to update the persmissions of a specific role
+// pass the `revision` of the group that was read to reject the change if the group changed in the meantime
 #[update(guard = "auth")]
 async fn edit_role_permissions(
     group_identifier: Principal,
     role_name: String,
     post_permissions: Vec<PostPermission>,
     member_identifier: Principal,
+    expected_revision: Option<u64>,
 ) -> Result<bool, ApiError> {
-    match Store::can_edit(caller(), group_identifier, member_identifier).await {
-        Ok(_caller) => {
-            Store::update_role_permissions(_caller, group_identifier, role_name, post_permissions)
-        }
+    match Store::can_manage_roles(caller(), group_identifier, member_identifier).await {
+        Ok(_caller) => Store::update_role_permissions(
+            _caller,
+            group_identifier,
+            role_name,
+            post_permissions,
+            expected_revision,
+        ),
         Err(err) => Err(err),
     }
 }
@@ -0,0 +1,107 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use candid::Principal;
+use ic_cdk::api::time;
+use ic_scalable_canister::ic_scalable_misc::helpers::role_helper::get_member_roles;
+
+// Cached roles are fetched again from the member canister after this period
+static ROLE_CACHE_TTL_SECONDS: u64 = 5 * 60;
+
+// Max number of cached members, the oldest entry is removed when the cache is full
+static MAX_CACHED_MEMBERS: usize = 10_000;
+
+struct CachedMemberRoles {
+    principal: Principal,
+    roles: Vec<String>,
+    cached_on: u64,
+}
+
+thread_local! {
+    // Heap only, the cache is empty after an upgrade
+    static ROLE_CACHE: RefCell<HashMap<(Principal, Principal), CachedMemberRoles>> = RefCell::new(HashMap::new());
+}
+
+// Cache of the roles of the members per group, so a permission check doesnt need a call to the member canister every time
+// the member canister invalidates a member when its roles change, the entries expire after `ROLE_CACHE_TTL_SECONDS` as a fallback
+pub struct MemberRoleCache;
+
+impl MemberRoleCache {
+    // Method to get the principal and roles of a member, from the cache if the entry is not expired
+    pub async fn get_member_roles(
+        member_identifier: Principal,
+        group_identifier: Principal,
+    ) -> Result<(Principal, Vec<String>), String> {
+        let cached = ROLE_CACHE.with(|cache| {
+            cache
+                .borrow()
+                .get(&(member_identifier, group_identifier))
+                .filter(|cached| cached.cached_on + ROLE_CACHE_TTL_SECONDS * 1_000_000_000 > time())
+                .map(|cached| (cached.principal, cached.roles.clone()))
+        });
+
+        match cached {
+            Some(_cached) => Ok(_cached),
+            None => Self::refresh(member_identifier, group_identifier).await,
+        }
+    }
+
+    // Method to fetch the roles of a member from the member canister and store them in the cache
+    pub async fn refresh(
+        member_identifier: Principal,
+        group_identifier: Principal,
+    ) -> Result<(Principal, Vec<String>), String> {
+        let result = get_member_roles(member_identifier, group_identifier).await;
+
+        match &result {
+            Ok((_principal, _roles)) => Self::insert(
+                member_identifier,
+                group_identifier,
+                CachedMemberRoles {
+                    principal: *_principal,
+                    roles: _roles.clone(),
+                    cached_on: time(),
+                },
+            ),
+            Err(_) => Self::invalidate(member_identifier, group_identifier),
+        }
+
+        result
+    }
+
+    pub fn invalidate(member_identifier: Principal, group_identifier: Principal) {
+        ROLE_CACHE.with(|cache| {
+            cache
+                .borrow_mut()
+                .remove(&(member_identifier, group_identifier))
+        });
+    }
+
+    // Method to remove the cached roles of all members of a group, used when a role of the group is renamed or removed
+    pub fn invalidate_group(group_identifier: Principal) {
+        ROLE_CACHE.with(|cache| {
+            cache
+                .borrow_mut()
+                .retain(|(_, _group_identifier), _| _group_identifier != &group_identifier)
+        });
+    }
+
+    fn insert(member_identifier: Principal, group_identifier: Principal, roles: CachedMemberRoles) {
+        ROLE_CACHE.with(|cache| {
+            let mut cache = cache.borrow_mut();
+            if cache.len() >= MAX_CACHED_MEMBERS
+                && !cache.contains_key(&(member_identifier, group_identifier))
+            {
+                let oldest = cache
+                    .iter()
+                    .min_by_key(|(_, cached)| cached.cached_on)
+                    .map(|(key, _)| *key);
+
+                if let Some(_oldest) = oldest {
+                    cache.remove(&_oldest);
+                }
+            }
+
+            cache.insert((member_identifier, group_identifier), roles);
+        });
+    }
+}
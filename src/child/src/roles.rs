@@ -0,0 +1,287 @@
+use candid::Principal;
+use ic_scalable_canister::ic_scalable_misc::{
+    helpers::role_helper::get_read_only_permissions,
+    models::{
+        group_role::GroupRole,
+        permissions_models::{Permission, PermissionActionType, PermissionActions},
+    },
+};
+
+// Rank of the default roles, a lower rank is a higher role
+// custom roles are ranked below the default roles by their index, the member role is ranked last
+static OWNER_RANK: u64 = 0;
+static ADMIN_RANK: u64 = 1;
+static MODERATOR_RANK: u64 = 2;
+static CUSTOM_ROLE_RANK: u64 = 3;
+static MEMBER_RANK: u64 = u64::MAX;
+
+// Caller that is allowed to manage roles, only roles ranked below `rank` can be managed
+// `roles` are the roles of the caller, `None` for the owner who holds every permission
+pub struct RoleManager {
+    pub principal: Principal,
+    pub rank: u64,
+    pub roles: Option<Vec<String>>,
+}
+
+impl RoleManager {
+    pub fn owner(principal: Principal) -> Self {
+        RoleManager {
+            principal,
+            rank: OWNER_RANK,
+            roles: None,
+        }
+    }
+
+    // Method to check if a role with the passed rank can be created, edited, assigned or removed by the manager
+    pub fn can_manage(&self, rank: u64) -> bool {
+        rank > self.rank
+    }
+
+    // Method to check if the manager holds every action of the permission, a manager can only hand out what it holds itself
+    // the actions of the read only permissions every role gets by default can always be handed out
+    pub fn can_grant(&self, group_roles: &[GroupRole], permission: &Permission) -> bool {
+        let member_roles = match &self.roles {
+            None => return true,
+            Some(_roles) => _roles,
+        };
+
+        let default_actions = get_read_only_permissions()
+            .into_iter()
+            .find(|p| p.name == permission.name)
+            .map(|p| p.actions);
+
+        [
+            (permission.actions.write, PermissionActionType::Write),
+            (permission.actions.read, PermissionActionType::Read),
+            (permission.actions.edit, PermissionActionType::Edit),
+            (permission.actions.delete, PermissionActionType::Delete),
+        ]
+        .iter()
+        .filter(|(is_set, _)| *is_set)
+        .all(|(_, action)| {
+            default_actions
+                .as_ref()
+                .is_some_and(|actions| GroupPermission::has_action(actions, action))
+                || match GroupPermission::from_name(&permission.name) {
+                    Some(_permission) => _permission.is_granted(member_roles, group_roles, action),
+                    None => member_roles
+                        .iter()
+                        .filter_map(|member_role| {
+                            group_roles.iter().find(|role| &role.name == member_role)
+                        })
+                        .flat_map(|role| role.permissions.iter())
+                        .any(|p| {
+                            p.name == permission.name
+                                && GroupPermission::has_action(&p.actions, action)
+                        }),
+                }
+        })
+    }
+}
+
+// Caller that passed a profile permission check, `roles` is `None` if the caller was the owner
+// the permissions are checked again against the stored group right before the write, the group can change while the roles are fetched
+pub struct GroupEditor {
+    pub principal: Principal,
+    pub roles: Option<Vec<String>>,
+}
+
+impl GroupEditor {
+    // Method to check if the editor has every permission for the action on the current state of the group
+    pub fn has_permissions(
+        &self,
+        owner: Principal,
+        group_roles: &[GroupRole],
+        permissions: &[GroupPermission],
+        action: &PermissionActionType,
+    ) -> bool {
+        if owner == self.principal {
+            return true;
+        }
+
+        match &self.roles {
+            Some(_roles) => permissions
+                .iter()
+                .all(|permission| permission.is_granted(_roles, group_roles, action)),
+            None => false,
+        }
+    }
+}
+
+// Hierarchy of the roles of a group, based on the default roles and the index of the custom roles
+pub struct RoleHierarchy;
+
+impl RoleHierarchy {
+    // Method to get the rank of a role, unknown roles are ranked as member
+    pub fn get_role_rank(group_roles: &[GroupRole], role_name: &str) -> u64 {
+        match role_name {
+            "owner" => OWNER_RANK,
+            "admin" => ADMIN_RANK,
+            "moderator" => MODERATOR_RANK,
+            "member" => MEMBER_RANK,
+            _ => group_roles
+                .iter()
+                .find(|role| role.name == role_name)
+                .map(|role| Self::get_custom_role_rank(role.index))
+                .unwrap_or(MEMBER_RANK),
+        }
+    }
+
+    // Custom roles without an index are ranked right above the member role
+    pub fn get_custom_role_rank(index: Option<u64>) -> u64 {
+        match index {
+            Some(_index) => CUSTOM_ROLE_RANK.saturating_add(_index).min(MEMBER_RANK - 1),
+            None => MEMBER_RANK - 1,
+        }
+    }
+
+    // Method to get the rank of the highest role of a member
+    pub fn get_member_rank(group_roles: &[GroupRole], member_roles: &[String]) -> u64 {
+        member_roles
+            .iter()
+            .map(|role_name| Self::get_role_rank(group_roles, role_name))
+            .min()
+            .unwrap_or(MEMBER_RANK)
+    }
+
+    // Method to get the rank of every role of a group, highest role first
+    pub fn get_role_ranks(group_roles: &[GroupRole]) -> Vec<(String, u64)> {
+        let mut ranks: Vec<(String, u64)> = ["owner", "admin", "moderator", "member"]
+            .iter()
+            .map(|role_name| role_name.to_string())
+            .chain(group_roles.iter().map(|role| role.name.clone()))
+            .map(|role_name| {
+                let rank = Self::get_role_rank(group_roles, &role_name);
+                (role_name, rank)
+            })
+            .collect();
+
+        ranks.sort_by_key(|(_, rank)| *rank);
+        ranks
+    }
+}
+
+// Permissions that are checked for group actions
+// the scoped permissions are stored on the roles by name, next to the generic `group` permission
+// a role without the profile, image or privacy permission falls back to its `group` permission
+// roles, wallets and deleting need an explicit grant, so the default roles (other than owner) cant do these
+pub enum GroupPermission {
+    Group,
+    // name, description, website, location and tags
+    Profile,
+    // image and banner image
+    Image,
+    // privacy and the gated amount
+    Privacy,
+    Role,
+    // the `invite` permission of the default roles, also checked by the member canister
+    Invite,
+    Wallet,
+    Delete,
+}
+
+impl GroupPermission {
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            GroupPermission::Group => "group",
+            GroupPermission::Profile => "group_profile",
+            GroupPermission::Image => "group_image",
+            GroupPermission::Privacy => "group_privacy",
+            GroupPermission::Role => "group_role",
+            GroupPermission::Invite => "invite",
+            GroupPermission::Wallet => "group_wallet",
+            GroupPermission::Delete => "group_delete",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<GroupPermission> {
+        match name {
+            "group" => Some(GroupPermission::Group),
+            "group_profile" => Some(GroupPermission::Profile),
+            "group_image" => Some(GroupPermission::Image),
+            "group_privacy" => Some(GroupPermission::Privacy),
+            "group_role" => Some(GroupPermission::Role),
+            "invite" => Some(GroupPermission::Invite),
+            "group_wallet" => Some(GroupPermission::Wallet),
+            "group_delete" => Some(GroupPermission::Delete),
+            _ => None,
+        }
+    }
+
+    // Method to check if any of the roles of a member grant the action for this permission
+    pub fn is_granted(
+        &self,
+        member_roles: &[String],
+        group_roles: &[GroupRole],
+        action: &PermissionActionType,
+    ) -> bool {
+        member_roles
+            .iter()
+            .filter_map(|member_role| group_roles.iter().find(|role| &role.name == member_role))
+            .any(|role| {
+                let permission = role
+                    .permissions
+                    .iter()
+                    .find(|p| p.name == self.get_name())
+                    .or_else(|| match self.has_group_fallback() {
+                        true => role
+                            .permissions
+                            .iter()
+                            .find(|p| p.name == GroupPermission::Group.get_name()),
+                        false => None,
+                    });
+
+                permission.is_some_and(|p| Self::has_action(&p.actions, action))
+            })
+    }
+
+    // The `group` permission covers the profile, roles and wallets, so the default admin can manage them
+    // deleting the group needs to be granted explicitly, `invite` is a permission of its own
+    fn has_group_fallback(&self) -> bool {
+        matches!(
+            self,
+            GroupPermission::Profile
+                | GroupPermission::Image
+                | GroupPermission::Privacy
+                | GroupPermission::Role
+                | GroupPermission::Wallet
+        )
+    }
+
+    fn has_action(actions: &PermissionActions, action: &PermissionActionType) -> bool {
+        match action {
+            PermissionActionType::Write => actions.write,
+            PermissionActionType::Read => actions.read,
+            PermissionActionType::Edit => actions.edit,
+            PermissionActionType::Delete => actions.delete,
+        }
+    }
+}
+
+// Pins the permissions of the default admin and moderator roles
+#[test]
+pub fn default_role_permissions() {
+    use ic_scalable_canister::ic_scalable_misc::helpers::role_helper::default_roles;
+
+    let group_roles = default_roles();
+    let admin = vec!["admin".to_string()];
+    let moderator = vec!["moderator".to_string()];
+    let edit = PermissionActionType::Edit;
+
+    for permission in [
+        GroupPermission::Group,
+        GroupPermission::Profile,
+        GroupPermission::Image,
+        GroupPermission::Privacy,
+        GroupPermission::Role,
+        GroupPermission::Wallet,
+    ] {
+        assert!(permission.is_granted(&admin, &group_roles, &edit));
+        assert!(!permission.is_granted(&moderator, &group_roles, &edit));
+    }
+
+    let delete = GroupPermission::Delete;
+    assert!(!delete.is_granted(&admin, &group_roles, &edit));
+    assert!(!delete.is_granted(&admin, &group_roles, &PermissionActionType::Delete));
+    assert!(!delete.is_granted(&moderator, &group_roles, &PermissionActionType::Delete));
+}
@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+
+use candid::Principal;
+use ic_stable_structures::StableBTreeMap;
+use shared::group_model::Group;
+
+use crate::store::{MEMORY_MANAGER, SEARCH_INDEX, SEARCH_INDEX_MEMORY_ID};
+
+// Weight of a token per field it is found in, a match on the name is worth more than a match on the description
+static NAME_WEIGHT: u32 = 3;
+static DESCRIPTION_WEIGHT: u32 = 1;
+static WEBSITE_WEIGHT: u32 = 1;
+
+// Tokens shorter than this are not indexed, longer tokens are cut off
+static MIN_TOKEN_LENGTH: usize = 2;
+static MAX_TOKEN_LENGTH: usize = 32;
+
+// Website tokens that are on (almost) every website and would match every group
+static IGNORED_WEBSITE_TOKENS: [&str; 3] = ["http", "https", "www"];
+
+// Inverted index over the name, description and website of the groups
+// the keys are `{token}:{group_identifier}` and the value is the weight of the token for that group
+// only groups that are not deleted are indexed
+pub struct SearchIndex;
+
+impl SearchIndex {
+    // Method to add the tokens of a group to the index
+    pub fn add_group(identifier: &str, group: &Group) {
+        if group.is_deleted {
+            return;
+        }
+
+        SEARCH_INDEX.with(|index| {
+            let mut index = index.borrow_mut();
+            for (token, weight) in Self::get_group_tokens(group) {
+                index.insert(Self::get_key(&token, identifier), weight);
+            }
+        });
+    }
+
+    // Method to remove the tokens of a group from the index
+    pub fn remove_group(identifier: &str, group: &Group) {
+        SEARCH_INDEX.with(|index| {
+            let mut index = index.borrow_mut();
+            for token in Self::get_group_tokens(group).keys() {
+                index.remove(&Self::get_key(token, identifier));
+            }
+        });
+    }
+
+    // Method to rebuild the index from scratch, used when entries are added without going through the store
+    pub fn rebuild(groups: &[(String, Group)]) {
+        SEARCH_INDEX.with(|index| {
+            index.replace(StableBTreeMap::new(
+                MEMORY_MANAGER.with(|m| m.borrow().get(SEARCH_INDEX_MEMORY_ID)),
+            ))
+        });
+
+        for (identifier, group) in groups {
+            Self::add_group(identifier, group);
+        }
+    }
+
+    // Method to search the index, returns the matching groups with their relevance score
+    // every token of the query needs to match (the start of) a token of the group
+    // returns `None` if the query has no searchable tokens
+    pub fn search(query: &str) -> Option<HashMap<Principal, u32>> {
+        let query_tokens = Self::tokenize(query);
+        if query_tokens.is_empty() {
+            return None;
+        }
+
+        let mut scores: HashMap<String, u32> = HashMap::new();
+        for (position, query_token) in query_tokens.iter().enumerate() {
+            // The best matching token of a group counts for the score of this query token
+            let mut token_scores: HashMap<String, u32> = HashMap::new();
+            SEARCH_INDEX.with(|index| {
+                index
+                    .borrow()
+                    .range(query_token.clone()..)
+                    .take_while(|(key, _)| key.starts_with(query_token.as_str()))
+                    .for_each(|(key, weight)| {
+                        if let Some((token, identifier)) = key.rsplit_once(':') {
+                            // An exact match is worth more than a prefix match
+                            let score = if token == query_token {
+                                weight * 2
+                            } else {
+                                weight
+                            };
+                            let best = token_scores.entry(identifier.to_string()).or_default();
+                            *best = (*best).max(score);
+                        }
+                    });
+            });
+
+            // Only keep the groups that matched all the previous query tokens
+            scores = if position == 0 {
+                token_scores
+            } else {
+                token_scores
+                    .into_iter()
+                    .filter_map(|(identifier, score)| {
+                        scores
+                            .get(&identifier)
+                            .map(|previous| (identifier, previous + score))
+                    })
+                    .collect()
+            };
+        }
+
+        Some(
+            scores
+                .into_iter()
+                .filter_map(|(identifier, score)| {
+                    Principal::from_text(identifier)
+                        .ok()
+                        .map(|identifier| (identifier, score))
+                })
+                .collect(),
+        )
+    }
+
+    // Method to get the tokens of a group with their weight
+    fn get_group_tokens(group: &Group) -> HashMap<String, u32> {
+        let mut tokens: HashMap<String, u32> = HashMap::new();
+
+        for token in Self::tokenize(&group.name) {
+            *tokens.entry(token).or_default() += NAME_WEIGHT;
+        }
+
+        for token in Self::tokenize(&group.description) {
+            *tokens.entry(token).or_default() += DESCRIPTION_WEIGHT;
+        }
+
+        for token in Self::tokenize(&group.website) {
+            if !IGNORED_WEBSITE_TOKENS.contains(&token.as_str()) {
+                *tokens.entry(token).or_default() += WEBSITE_WEIGHT;
+            }
+        }
+
+        tokens
+    }
+
+    // Method to split a text into unique lowercase alphanumeric tokens
+    fn tokenize(text: &str) -> Vec<String> {
+        let mut tokens: Vec<String> = text
+            .to_lowercase()
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|token| token.chars().count() >= MIN_TOKEN_LENGTH)
+            .map(|token| token.chars().take(MAX_TOKEN_LENGTH).collect())
+            .collect();
+
+        tokens.sort();
+        tokens.dedup();
+        tokens
+    }
+
+    fn get_key(token: &str, identifier: &str) -> String {
+        format!("{}:{}", token, identifier)
+    }
+}
+
+// Splits on every non alphanumeric character, drops short tokens and removes duplicates
+#[test]
+pub fn tokenize() {
+    assert_eq!(
+        SearchIndex::tokenize("Rust-Lang, rust & a Café!"),
+        vec!["café", "lang", "rust"]
+    );
+    assert_eq!(
+        SearchIndex::tokenize(&"x".repeat(40)),
+        vec!["x".repeat(MAX_TOKEN_LENGTH)]
+    );
+    assert!(SearchIndex::tokenize("a - b").is_empty());
+}
+
+// Every query token needs to match, a name match and an exact match score higher
+#[test]
+pub fn search() {
+    let rust = Principal::from_slice(&[1]);
+    let rustacean = Principal::from_slice(&[2]);
+    let deleted = Principal::from_slice(&[3]);
+    let rust_group = Group {
+        name: "Rust builders".to_string(),
+        description: "Weekly meetups".to_string(),
+        website: "https://www.rust.example".to_string(),
+        ..Default::default()
+    };
+
+    SearchIndex::add_group(&rust.to_string(), &rust_group);
+    SearchIndex::add_group(
+        &rustacean.to_string(),
+        &Group {
+            name: "Builders".to_string(),
+            description: "For every rustacean".to_string(),
+            ..Default::default()
+        },
+    );
+    SearchIndex::add_group(
+        &deleted.to_string(),
+        &Group {
+            name: "Rust".to_string(),
+            is_deleted: true,
+            ..Default::default()
+        },
+    );
+
+    let scores = SearchIndex::search("RUST").unwrap();
+    assert_eq!(scores.len(), 2);
+    assert!(scores[&rust] > scores[&rustacean]);
+
+    let scores = SearchIndex::search("rust meetups").unwrap();
+    assert_eq!(scores.keys().collect::<Vec<_>>(), vec![&rust]);
+
+    assert!(SearchIndex::search("https").unwrap().is_empty());
+    assert!(SearchIndex::search("?!").is_none());
+
+    SearchIndex::remove_group(&rust.to_string(), &rust_group);
+    let scores = SearchIndex::search("rust").unwrap();
+    assert_eq!(scores.keys().collect::<Vec<_>>(), vec![&rustacean]);
+}
@@ -1,18 +1,20 @@
-use std::{collections::HashMap, iter::FromIterator, vec};
+use std::{cmp::Ordering, collections::HashMap, iter::FromIterator, time::Duration, vec};
 
 use candid::Principal;
+use futures::future::join_all;
 use ic_cdk::api::{self, call, time};
+use ic_cdk_timers::set_timer_interval;
 use ic_scalable_canister::ic_scalable_misc::{
     enums::{
         api_error_type::{ApiError, ApiErrorType},
         filter_type::FilterType,
+        location_type::Location,
         privacy_type::{GatedType, NeuronGatedRules, Privacy, TokenGated},
-        sort_type::SortDirection,
     },
     helpers::{
         error_helper::api_error,
         paging_helper::get_paged_data,
-        role_helper::{default_roles, get_member_roles, get_read_only_permissions, has_permission},
+        role_helper::{default_roles, get_member_roles, get_read_only_permissions},
         serialize_helper::serialize,
         token_canister_helper::{
             dip20_balance_of, dip721_balance_of, ext_balance_of, legacy_dip721_balance_of,
@@ -21,17 +23,36 @@ use ic_scalable_canister::ic_scalable_misc::{
     models::{
         group_role::GroupRole,
         identifier_model::Identifier,
-        neuron_models::{DissolveState, ListNeurons, ListNeuronsResponse},
+        neuron_models::{DissolveState, ListNeurons, ListNeuronsResponse, Neuron},
         paged_response_models::PagedResponse,
-        permissions_models::{Permission, PermissionActionType, PermissionType, PostPermission},
+        permissions_models::{Permission, PermissionActionType, PostPermission},
     },
 };
 use ic_scalable_canister::store::Data;
-
-use shared::group_model::{Group, GroupFilter, GroupResponse, GroupSort, PostGroup, UpdateGroup};
+use serde::Serialize;
+
+use shared::group_model::{
+    DeletionPolicy, Group, GroupAuditRecord, GroupChunkQuery, GroupFilter, GroupFilterExpression,
+    GroupInvite, GroupJob, GroupJobKind, GroupResponse, GroupSort, GroupSortKey, GroupVersion,
+    JoinEligibility, JoinGrant, JoinRequirement, NeuronEligibility, NeuronRequirement,
+    OwnershipTransferOffer, PatchGroup, PostGroup, RoleGrant, SortedGroups, TokenRequirement,
+    UpdateGroup,
+};
 use std::cell::RefCell;
 
-use crate::{validation::validate_post_group, IDENTIFIER_KIND};
+use crate::{
+    audit::AuditLog,
+    grants::RoleGrants,
+    index::GroupIndex,
+    invites::GroupInvites,
+    jobs::Jobs,
+    role_cache::MemberRoleCache,
+    roles::{GroupEditor, GroupPermission, RoleHierarchy, RoleManager},
+    search::SearchIndex,
+    validation::validate_post_group,
+    versions::GroupVersions,
+    IDENTIFIER_KIND,
+};
 
 use super::validation::validate_update_group;
 use ic_stable_structures::{
@@ -41,8 +62,46 @@ use ic_stable_structures::{
 
 type Memory = VirtualMemory<DefaultMemoryImpl>;
 
+// The results of the `Search` filters of a query by search value, `None` if the value has no searchable words
+type GroupSearches = HashMap<String, Option<HashMap<Principal, u32>>>;
+
 pub static DATA_MEMORY_ID: MemoryId = MemoryId::new(0);
 pub static ENTRIES_MEMORY_ID: MemoryId = MemoryId::new(1);
+pub static SEARCH_INDEX_MEMORY_ID: MemoryId = MemoryId::new(2);
+pub static TAG_INDEX_MEMORY_ID: MemoryId = MemoryId::new(3);
+pub static OWNER_INDEX_MEMORY_ID: MemoryId = MemoryId::new(4);
+pub static CREATED_ON_INDEX_MEMORY_ID: MemoryId = MemoryId::new(5);
+pub static DELETION_POLICY_MEMORY_ID: MemoryId = MemoryId::new(6);
+pub static DEPENDENT_CANISTERS_MEMORY_ID: MemoryId = MemoryId::new(7);
+pub static JOBS_MEMORY_ID: MemoryId = MemoryId::new(8);
+pub static AUDIT_LOG_MEMORY_ID: MemoryId = MemoryId::new(9);
+pub static GROUP_VERSIONS_MEMORY_ID: MemoryId = MemoryId::new(10);
+pub static ROLE_GRANTS_MEMORY_ID: MemoryId = MemoryId::new(11);
+pub static INVITES_MEMORY_ID: MemoryId = MemoryId::new(12);
+pub static INVITE_SECRET_MEMORY_ID: MemoryId = MemoryId::new(13);
+pub static DELETED_INDEX_MEMORY_ID: MemoryId = MemoryId::new(14);
+pub static INVITE_INDEX_MEMORY_ID: MemoryId = MemoryId::new(15);
+
+// Default and maximum time a proposed owner has to accept the ownership of a group
+pub static OWNERSHIP_TRANSFER_DEFAULT_EXPIRY_SECONDS: u64 = 7 * 24 * 60 * 60;
+pub static OWNERSHIP_TRANSFER_MAX_EXPIRY_SECONDS: u64 = 30 * 24 * 60 * 60;
+
+// Interval of the job that removes deleted groups past the retention period, and the max groups removed per run
+static PURGE_INTERVAL_SECONDS: u64 = 60 * 60;
+static PURGE_BATCH_SIZE: usize = 100;
+
+// Max retention period of deleted groups, so the retention time in nanoseconds fits in a u64
+static MAX_RETENTION_PERIOD_SECONDS: u64 = 10 * 365 * 24 * 60 * 60;
+
+// Interval of the job that retries the failed inter-canister calls
+static JOB_RETRY_INTERVAL_SECONDS: u64 = 5 * 60;
+
+// Interval of the job that revokes the expired temporary roles
+static GRANT_EXPIRY_INTERVAL_SECONDS: u64 = 60;
+
+// Mean radius of the earth, used for the distance between group locations
+static EARTH_RADIUS_KM: f64 = 6371.0;
+
 thread_local! {
     pub static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
         RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
@@ -60,6 +119,101 @@ thread_local! {
             MEMORY_MANAGER.with(|m| m.borrow().get(ENTRIES_MEMORY_ID)),
         )
     );
+
+    // Full-text search index, see `search.rs`
+    pub static SEARCH_INDEX: RefCell<StableBTreeMap<String, u32, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(SEARCH_INDEX_MEMORY_ID)),
+        )
+    );
+
+    // Secondary indexes used for filtering, see `index.rs`
+    pub static TAG_INDEX: RefCell<StableBTreeMap<String, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(TAG_INDEX_MEMORY_ID)),
+        )
+    );
+
+    pub static OWNER_INDEX: RefCell<StableBTreeMap<String, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(OWNER_INDEX_MEMORY_ID)),
+        )
+    );
+
+    pub static CREATED_ON_INDEX: RefCell<StableBTreeMap<String, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(CREATED_ON_INDEX_MEMORY_ID)),
+        )
+    );
+
+    pub static DELETED_INDEX: RefCell<StableBTreeMap<String, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(DELETED_INDEX_MEMORY_ID)),
+        )
+    );
+
+    pub static DELETION_POLICY: RefCell<StableCell<DeletionPolicy, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(DELETION_POLICY_MEMORY_ID)),
+            DeletionPolicy::default(),
+        ).expect("failed")
+    );
+
+    // Canisters that are notified when a group is deleted or restored, keyed by principal text
+    pub static DEPENDENT_CANISTERS: RefCell<StableBTreeMap<String, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(DEPENDENT_CANISTERS_MEMORY_ID)),
+        )
+    );
+
+    // Tracked inter-canister calls, see `jobs.rs`
+    pub static JOBS: RefCell<StableBTreeMap<u64, GroupJob, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(JOBS_MEMORY_ID)),
+        )
+    );
+
+    // Append-only log of the group changes, see `audit.rs`
+    pub static AUDIT_LOG: RefCell<StableBTreeMap<String, GroupAuditRecord, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(AUDIT_LOG_MEMORY_ID)),
+        )
+    );
+
+    // Earlier versions of the group profiles, see `versions.rs`
+    pub static GROUP_VERSIONS: RefCell<StableBTreeMap<String, GroupVersion, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(GROUP_VERSIONS_MEMORY_ID)),
+        )
+    );
+
+    // Temporary roles of the members, see `grants.rs`
+    pub static ROLE_GRANTS: RefCell<StableBTreeMap<String, RoleGrant, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(ROLE_GRANTS_MEMORY_ID)),
+        )
+    );
+
+    // Invite codes of the groups, see `invites.rs`
+    pub static INVITES: RefCell<StableBTreeMap<String, GroupInvite, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(INVITES_MEMORY_ID)),
+        )
+    );
+
+    pub static INVITE_INDEX: RefCell<StableBTreeMap<String, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(INVITE_INDEX_MEMORY_ID)),
+        )
+    );
+
+    // Secret that is used to sign the join grants, empty until the first invite is redeemed
+    pub static INVITE_SECRET: RefCell<StableCell<Vec<u8>, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(INVITE_SECRET_MEMORY_ID)),
+            vec![],
+        ).expect("failed")
+    );
 }
 
 pub struct Store;
@@ -94,6 +248,9 @@ impl Store {
             created_on: time(),
             wallets: HashMap::new(),
             privacy_gated_type_amount: temp_group.privacy_gated_type_amount,
+            pending_ownership_transfer: None,
+            deleted_on: None,
+            revision: None,
         };
 
         let add_entry_result = match Self::validate_group_privacy(
@@ -149,6 +306,14 @@ impl Store {
                         Err(err)
                     }
                     Ok((_identifier, _group_data)) => {
+                        Self::add_to_indexes(&_identifier.to_string(), &_group_data);
+                        AuditLog::add_record(
+                            caller,
+                            "add_group",
+                            _identifier,
+                            None,
+                            Some(&_group_data),
+                        );
                         // If successfull return the group data
                         Ok(Self::map_group_to_group_response(
                             _identifier.to_string(),
@@ -162,10 +327,131 @@ impl Store {
 
     // Method to update a group in the data store
     pub fn update_group(
-        caller: Principal,
+        editor: GroupEditor,
+        group_identifier: Principal,
+        update_group: UpdateGroup,
+    ) -> Result<GroupResponse, ApiError> {
+        Self::set_group_profile(editor, group_identifier, update_group, "update_group")
+    }
+
+    // Method to update only the passed fields of a group
+    // returns a conflict error if `expected_updated_on` is passed and the group changed after that time
+    pub fn patch_group(
+        editor: GroupEditor,
+        group_identifier: Principal,
+        patch_group: PatchGroup,
+    ) -> Result<GroupResponse, ApiError> {
+        let inputs = Some(vec![
+            format!("caller - {:?}", &editor.principal),
+            format!("group_identifier - {:?}", &group_identifier),
+            format!("patch_group - {:?}", &patch_group),
+        ]);
+
+        let (_, _group_data) = STABLE_DATA.with(|data| {
+            ENTRIES.with(|entries| Data::get_entry(data, entries, group_identifier))
+        })?;
+
+        if let Some(_expected_updated_on) = patch_group.expected_updated_on {
+            if _expected_updated_on != _group_data.updated_on {
+                return Err(api_error(
+                    ApiErrorType::BadRequest,
+                    "UPDATE_CONFLICT",
+                    "The group was changed after it was read",
+                    STABLE_DATA
+                        .with(|data| Data::get_name(data.borrow().get()))
+                        .as_str(),
+                    "patch_group",
+                    inputs,
+                ));
+            }
+        }
+
+        let update_group = Self::get_patched_profile(_group_data, patch_group);
+        Self::set_group_profile(editor, group_identifier, update_group, "patch_group")
+    }
+
+    // Method to get the profile a group gets when the patch is applied, used to check the permissions of the patch
+    pub fn get_group_patched_profile(
+        group_identifier: Principal,
+        patch_group: PatchGroup,
+    ) -> Result<UpdateGroup, ApiError> {
+        let (_, _group_data) = STABLE_DATA.with(|data| {
+            ENTRIES.with(|entries| Data::get_entry(data, entries, group_identifier))
+        })?;
+        Ok(Self::get_patched_profile(_group_data, patch_group))
+    }
+
+    // Method to get the profile of a group with the passed fields of the patch applied
+    fn get_patched_profile(group: Group, patch_group: PatchGroup) -> UpdateGroup {
+        UpdateGroup {
+            name: patch_group.name.unwrap_or(group.name),
+            description: patch_group.description.unwrap_or(group.description),
+            website: patch_group.website.unwrap_or(group.website),
+            location: patch_group.location.unwrap_or(group.location),
+            privacy: patch_group.privacy.unwrap_or(group.privacy),
+            image: patch_group.image.unwrap_or(group.image),
+            privacy_gated_type_amount: patch_group
+                .privacy_gated_type_amount
+                .unwrap_or(group.privacy_gated_type_amount),
+            banner_image: patch_group.banner_image.unwrap_or(group.banner_image),
+            tags: patch_group.tags.unwrap_or(group.tags),
+        }
+    }
+
+    // Method to get the earlier versions of the profile of a group, newest first
+    pub fn get_group_versions(group_identifier: Principal) -> Vec<GroupVersion> {
+        GroupVersions::get_versions(group_identifier)
+    }
+
+    // Method to set the profile of a group back to an earlier version, the revert is added as a new version
+    pub fn revert_group_to_version(
+        editor: GroupEditor,
+        group_identifier: Principal,
+        version: u64,
+    ) -> Result<GroupResponse, ApiError> {
+        let _version = Self::get_group_version(group_identifier, version)?;
+        Self::set_group_profile(
+            editor,
+            group_identifier,
+            _version.profile,
+            "revert_group_to_version",
+        )
+    }
+
+    // Method to get an earlier version of the profile of a group
+    pub fn get_group_version(
+        group_identifier: Principal,
+        version: u64,
+    ) -> Result<GroupVersion, ApiError> {
+        let inputs = Some(vec![
+            format!("group_identifier - {:?}", &group_identifier),
+            format!("version - {:?}", &version),
+        ]);
+
+        match GroupVersions::get_version(group_identifier, version) {
+            None => Err(api_error(
+                ApiErrorType::NotFound,
+                "VERSION_NOT_FOUND",
+                "The version cant be found for this group",
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                "get_group_version",
+                inputs,
+            )),
+            Some(_version) => Ok(_version),
+        }
+    }
+
+    // Method to set the profile fields of a group, every change is added to the version history
+    // the permissions of the editor are checked again against the stored group, so fields changed by others in the meantime cant be reverted
+    fn set_group_profile(
+        editor: GroupEditor,
         group_identifier: Principal,
         update_group: UpdateGroup,
+        method_name: &str,
     ) -> Result<GroupResponse, ApiError> {
+        let caller = editor.principal;
         let inputs = Some(vec![
             format!("caller - {:?}", &caller),
             format!("id - {:?}", &group_identifier),
@@ -188,10 +474,31 @@ impl Store {
                                 "DELETED_GROUP",
                                 "You cant update a deleted group",
                                 Data::get_name(data.borrow().get()).as_str(),
-                                "update_group",
+                                method_name,
+                                inputs,
+                            ));
+                        }
+
+                        let mut group_roles = _group_data.roles.clone();
+                        group_roles.append(&mut default_roles());
+                        if !editor.has_permissions(
+                            _group_data.owner,
+                            &group_roles,
+                            &Self::get_profile_permissions(&_group_data, &update_group),
+                            &PermissionActionType::Edit,
+                        ) {
+                            return Err(api_error(
+                                ApiErrorType::Unauthorized,
+                                "NO_PERMISSION",
+                                "No permission",
+                                Data::get_name(data.borrow().get()).as_str(),
+                                method_name,
                                 inputs,
                             ));
                         }
+
+                        let previous_group_data = _group_data.clone();
+
                         // Update group fields
                         _group_data.name = update_group.name;
                         _group_data.description = update_group.description;
@@ -205,12 +512,23 @@ impl Store {
                         _group_data.tags = update_group.tags;
                         _group_data.updated_on = time();
 
-                        let update_group_result = ENTRIES.with(|entries| {
-                            Data::update_entry(data, entries, group_identifier, _group_data)
-                        });
-                        match update_group_result {
+                        match Self::write_group(
+                            data,
+                            caller,
+                            method_name,
+                            group_identifier,
+                            &previous_group_data,
+                            _group_data,
+                            None,
+                        ) {
                             Err(err) => Err(err),
                             Ok((_identifier, _group_data)) => {
+                                GroupVersions::add_version(
+                                    caller,
+                                    _identifier,
+                                    &previous_group_data,
+                                    &_group_data,
+                                );
                                 Ok(Self::map_group_to_group_response(
                                     _identifier.to_string(),
                                     _group_data,
@@ -224,82 +542,59 @@ impl Store {
     }
 
     // Method to delete a group from the data store
+    // the caller needs to be checked with `can_delete` first
     pub fn delete_group(caller: Principal, identifier: Principal) -> Result<Group, ApiError> {
-        let inputs = Some(vec![
-            format!("caller - {:?}", &caller),
-            format!("id - {:?}", &identifier),
-        ]);
         STABLE_DATA.with(|data| {
             // Check if the group exists in the data store
             match ENTRIES.with(|entries| Data::get_entry(data, entries, identifier)) {
                 Err(err) => Err(err),
                 Ok((_identifier, mut _group_data)) => {
-                    // Check of the group owner is also the caller
-                    if _group_data.owner != caller {
-                        return Err(api_error(
-                            ApiErrorType::Unauthorized,
-                            "CANT_DELETE_GROUP",
-                            "Only the owner can delete the group",
-                            Data::get_name(data.borrow().get()).as_str(),
-                            "delete_group",
-                            inputs,
-                        ));
+                    let previous_group_data = _group_data.clone();
+
+                    // Keep the original deletion time if the group is deleted again
+                    if !_group_data.is_deleted {
+                        _group_data.deleted_on = Some(time());
                     }
-                    
                     _group_data.is_deleted = true;
                     _group_data.updated_on = time();
 
-                    let update_group_result = ENTRIES.with(|entries| {
-                        Data::update_entry(data, entries, _identifier, _group_data.clone())
-                    });
-
-                    match update_group_result {
+                    match Self::write_group(
+                        data,
+                        caller,
+                        "delete_group",
+                        _identifier,
+                        &previous_group_data,
+                        _group_data,
+                        None,
+                    ) {
                         Err(err) => Err(err),
-                        Ok(_) => Ok(_group_data),
-                    }
-                }
-            }
-        })
-    }
-
-    // Method to get a group with an identifier from the data store
-    pub fn get_group(identifier: Principal) -> Result<GroupResponse, ApiError> {
-        STABLE_DATA.with(|data| {
-            match ENTRIES.with(|entries| Data::get_entry(data, entries, identifier)) {
-                Err(err) => Err(err),
-                Ok((_identifier, _group_data)) => {
-                    if _group_data.is_deleted {
-                        return Err(api_error(
-                            ApiErrorType::NotFound,
-                            "GROUP_NOT_FOUND",
-                            "No group found",
-                            Data::get_name(data.borrow().get()).as_str(),
-                            "get_group",
-                            None,
-                        ));
+                        Ok((_identifier, _group_data)) => {
+                            // the group can still be restored, so the member canisters and dependent canisters keep their data of the group
+                            if !previous_group_data.is_deleted {
+                                Jobs::add_jobs(
+                                    _identifier,
+                                    GroupJobKind::GroupSoftDeleted,
+                                    Self::get_notified_canisters(&_group_data),
+                                );
+                            }
+                            Ok(_group_data)
+                        }
                     }
-
-                    Ok(Self::map_group_to_group_response(
-                        _identifier.to_string(),
-                        _group_data,
-                    ))
                 }
             }
         })
     }
 
-    pub fn add_wallet(
+    // Method to restore a soft-deleted group, only the owner can restore a group within the restore period
+    pub fn restore_group(
         caller: Principal,
         group_identifier: Principal,
-        wallet_canister: Principal,
-        description: String,
-    ) -> Result<(), ApiError> {
+    ) -> Result<GroupResponse, ApiError> {
         let inputs = Some(vec![
             format!("caller - {:?}", &caller),
             format!("group_identifier - {:?}", &group_identifier),
-            format!("wallet_canister - {:?}", &wallet_canister),
-            format!("description - {:?}", &description),
         ]);
+
         STABLE_DATA.with(|data| {
             match ENTRIES.with(|entries| Data::get_entry(data, entries, group_identifier)) {
                 Err(err) => Err(err),
@@ -307,116 +602,572 @@ impl Store {
                     if _group_data.owner != caller {
                         return Err(api_error(
                             ApiErrorType::Unauthorized,
-                            "CANT_ADD_WALLET",
-                            "Only the owner can add a wallet",
+                            "CANT_RESTORE_GROUP",
+                            "Only the owner can restore the group",
                             Data::get_name(data.borrow().get()).as_str(),
-                            "add_wallet",
+                            "restore_group",
                             inputs,
                         ));
                     }
 
-                    _group_data.wallets.insert(wallet_canister, description);
-                    _group_data.updated_on = time();
-
-                    let update_group_result = ENTRIES.with(|entries| {
-                        Data::update_entry(data, entries, _identifier, _group_data.clone())
-                    });
-
-                    match update_group_result {
-                        Err(err) => Err(err),
-                        Ok(_) => Ok(()),
+                    if !_group_data.is_deleted {
+                        return Err(api_error(
+                            ApiErrorType::BadRequest,
+                            "GROUP_NOT_DELETED",
+                            "The group is not deleted",
+                            Data::get_name(data.borrow().get()).as_str(),
+                            "restore_group",
+                            inputs,
+                        ));
                     }
-                }
-            }
-        })
-    }
 
-    pub fn remove_wallet(
-        caller: Principal,
-        group_identifier: Principal,
-        wallet_canister: Principal,
-    ) -> Result<(), ApiError> {
-        let inputs = Some(vec![
-            format!("caller - {:?}", &caller),
-            format!("group_identifier - {:?}", &group_identifier),
-            format!("wallet_canister - {:?}", &wallet_canister),
-        ]);
-        STABLE_DATA.with(|data| {
-            // Check if the group exists in the data store
-            match ENTRIES.with(|entries| Data::get_entry(data, entries, group_identifier)) {
-                Err(err) => Err(err),
-                Ok((_identifier, mut _group_data)) => {
-                    if _group_data.owner != caller {
+                    if !Self::is_restorable(&_group_data) {
                         return Err(api_error(
-                            ApiErrorType::Unauthorized,
-                            "CANT_DELETE_WALLET",
-                            "Only the owner can delete a wallet",
+                            ApiErrorType::BadRequest,
+                            "RESTORE_PERIOD_EXPIRED",
+                            "The restore period of the group has expired",
                             Data::get_name(data.borrow().get()).as_str(),
-                            "remove_wallet",
+                            "restore_group",
                             inputs,
                         ));
                     }
 
-                    _group_data.wallets.remove(&wallet_canister);
+                    let previous_group_data = _group_data.clone();
+                    _group_data.is_deleted = false;
+                    _group_data.deleted_on = None;
                     _group_data.updated_on = time();
 
-                    let update_group_result = ENTRIES.with(|entries| {
-                        Data::update_entry(data, entries, _identifier, _group_data.clone())
-                    });
-
-                    match update_group_result {
+                    match Self::write_group(
+                        data,
+                        caller,
+                        "restore_group",
+                        _identifier,
+                        &previous_group_data,
+                        _group_data,
+                        None,
+                    ) {
                         Err(err) => Err(err),
-                        Ok(_) => Ok(()),
+                        Ok((_identifier, _group_data)) => {
+                            Jobs::add_jobs(
+                                _identifier,
+                                GroupJobKind::GroupRestored,
+                                Self::get_notified_canisters(&_group_data),
+                            );
+                            Ok(Self::map_group_to_group_response(
+                                _identifier.to_string(),
+                                _group_data,
+                            ))
+                        }
                     }
                 }
             }
         })
     }
 
-    // Method to get a group with an identifier from the data store
-    pub fn get_group_owner_and_privacy(
-        identifier: Principal,
-    ) -> Result<(Principal, Privacy), ApiError> {
-        STABLE_DATA.with(|data| {
-            match ENTRIES.with(|entries| Data::get_entry(data, entries, identifier)) {
-                Err(err) => Err(err),
-                Ok((_, _group_data)) => Ok((_group_data.owner, _group_data.privacy)),
+    // Method to get the soft-deleted groups that can still be restored
+    // returns the groups owned by the caller, and the groups the member has the delete permission for if a member identifier is passed
+    pub async fn get_deleted_groups(
+        caller: Principal,
+        member_identifier: Option<Principal>,
+    ) -> Vec<GroupResponse> {
+        // The groups of the caller come from the index, so no member canister call is needed for them
+        let mut identifiers = GroupIndex::get_deleted_identifiers(Some(caller));
+
+        if let Some(_member_identifier) = member_identifier {
+            // Only the groups that have members on the canister of the member and a role with the delete permission can be listed
+            // the roles of the member are fetched for those groups at the same time and checked locally
+            let member_canister = RoleGrants::get_member_canister(_member_identifier);
+            let delete = PermissionActionType::Delete;
+            let candidates: Vec<(Principal, Vec<GroupRole>)> =
+                GroupIndex::get_deleted_identifiers(None)
+                    .difference(&identifiers)
+                    .filter_map(|_identifier| {
+                        let _group_data =
+                            ENTRIES.with(|entries| entries.borrow().get(_identifier))?;
+                        let mut group_roles = _group_data.roles.clone();
+                        group_roles.append(&mut default_roles());
+
+                        let can_be_granted = group_roles.iter().any(|role| {
+                            GroupPermission::Delete.is_granted(
+                                std::slice::from_ref(&role.name),
+                                &group_roles,
+                                &delete,
+                            )
+                        });
+                        if !can_be_granted
+                            || !_group_data.member_count.contains_key(&member_canister)
+                        {
+                            return None;
+                        }
+
+                        Principal::from_text(_identifier)
+                            .ok()
+                            .map(|_identifier| (_identifier, group_roles))
+                    })
+                    .collect();
+
+            let member_roles = join_all(candidates.iter().map(|(_identifier, _)| {
+                MemberRoleCache::get_member_roles(_member_identifier, *_identifier)
+            }))
+            .await;
+
+            for ((_identifier, group_roles), member_roles) in
+                candidates.into_iter().zip(member_roles)
+            {
+                if let Ok((_principal, _roles)) = member_roles {
+                    let _roles =
+                        RoleGrants::remove_expired_roles(_identifier, _member_identifier, _roles);
+                    if _principal == caller
+                        && GroupPermission::Delete.is_granted(&_roles, &group_roles, &delete)
+                    {
+                        identifiers.insert(_identifier.to_string());
+                    }
+                }
             }
+        }
+
+        ENTRIES.with(|entries| {
+            let entries = entries.borrow();
+            identifiers
+                .into_iter()
+                .filter_map(|_identifier| {
+                    entries
+                        .get(&_identifier)
+                        .map(|_group_data| (_identifier, _group_data))
+                })
+                .filter(|(_, _group_data)| {
+                    _group_data.is_deleted && Self::is_restorable(_group_data)
+                })
+                .map(|(_identifier, _group_data)| {
+                    Self::map_group_to_group_response(_identifier, _group_data)
+                })
+                .collect()
         })
     }
 
-    // This method is used to get groups filtered and sorted with pagination
-    pub fn get_groups(
+    // Method to get the audit log of a group, newest first
+    // the owner can always see the log, moderators and admins need to pass their member identifier
+    pub async fn get_group_audit_log(
+        caller: Principal,
+        group_identifier: Principal,
+        member_identifier: Option<Principal>,
         limit: usize,
         page: usize,
-        filters: Vec<GroupFilter>,
-        filter_type: FilterType,
-        sort: GroupSort,
-        include_invite_only: bool,
-    ) -> PagedResponse<GroupResponse> {
-        let groups = ENTRIES.with(|data| Data::get_entries(data));
-        // Get groups for filtering and sorting
-        let mapped_groups: Vec<GroupResponse> = groups
-            .iter()
-            // Filter out deleted groups
-            .filter(|(_identifier, _group_data)| !_group_data.is_deleted)
-            .filter(|(_identifier, _group_data)| {
-                if include_invite_only {
-                    true
-                } else {
-                    _group_data.privacy != Privacy::InviteOnly
+    ) -> Result<PagedResponse<GroupAuditRecord>, ApiError> {
+        let inputs = Some(vec![
+            format!("caller - {:?}", &caller),
+            format!("group_identifier - {:?}", &group_identifier),
+            format!("member_identifier - {:?}", &member_identifier),
+        ]);
+
+        let (_, _group_data) = STABLE_DATA.with(|data| {
+            ENTRIES.with(|entries| Data::get_entry(data, entries, group_identifier))
+        })?;
+
+        let is_allowed = _group_data.owner == caller
+            || match member_identifier {
+                Some(_member_identifier) => {
+                    match MemberRoleCache::get_member_roles(_member_identifier, group_identifier)
+                        .await
+                    {
+                        Ok((_principal, _roles)) => {
+                            _principal == caller
+                                && _roles.iter().any(|role| {
+                                    role == "owner" || role == "admin" || role == "moderator"
+                                })
+                        }
+                        Err(_) => false,
+                    }
                 }
+                None => false,
+            };
+
+        if !is_allowed {
+            return Err(api_error(
+                ApiErrorType::Unauthorized,
+                "UNAUTHORIZED",
+                "Only the owner, admins and moderators can see the audit log",
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                "get_group_audit_log",
+                inputs,
+            ));
+        }
+
+        Ok(AuditLog::get_paged_records(group_identifier, limit, page))
+    }
+
+    // Method to permanently remove the soft-deleted groups that are past the retention period
+    // a limited number of groups is removed per run to stay within the instruction limit, returns the number of removed groups
+    pub fn purge_deleted_groups() -> usize {
+        let retention_period =
+            DELETION_POLICY.with(|policy| policy.borrow().get().retention_period_seconds);
+
+        let expired_groups: Vec<(Principal, Group)> = GroupIndex::get_deleted_identifiers(None)
+            .into_iter()
+            .filter_map(|_identifier| {
+                ENTRIES
+                    .with(|entries| entries.borrow().get(&_identifier))
+                    .map(|_group_data| (_identifier, _group_data))
+            })
+            .filter(|(_, _group_data)| {
+                _group_data.is_deleted
+                    && Self::get_deleted_on(_group_data)
+                        .saturating_add(retention_period.saturating_mul(1_000_000_000))
+                        < time()
+            })
+            .filter_map(|(_identifier, _group_data)| {
+                Principal::from_text(_identifier)
+                    .ok()
+                    .map(|_identifier| (_identifier, _group_data))
+            })
+            .take(PURGE_BATCH_SIZE)
+            .collect();
+
+        // the member canisters and dependent canisters can remove their data of the group now
+        for (_identifier, _group_data) in &expired_groups {
+            Self::remove_from_indexes(&_identifier.to_string(), _group_data);
+            ENTRIES.with(|entries| Data::remove_entry(entries, _identifier));
+            GroupVersions::remove_versions(*_identifier);
+            RoleGrants::remove_grants(*_identifier);
+            GroupInvites::remove_invites(*_identifier);
+            AuditLog::remove_records(*_identifier);
+            Jobs::remove_group_jobs(*_identifier);
+            Jobs::add_jobs(
+                *_identifier,
+                GroupJobKind::GroupDeleted,
+                Self::get_notified_canisters(_group_data),
+            );
+        }
+
+        expired_groups.len()
+    }
+
+    // Method to get the member canisters of a group and the dependent canisters, these are notified when a group is deleted or restored
+    fn get_notified_canisters(group: &Group) -> Vec<Principal> {
+        let mut canisters: Vec<Principal> = group.member_count.keys().cloned().collect();
+        canisters.extend(Jobs::get_dependent_canisters());
+        canisters
+    }
+
+    // Method to start the recurring jobs, timers are not kept on upgrade so this is called on init and post upgrade
+    pub fn start_timers() {
+        set_timer_interval(Duration::from_secs(PURGE_INTERVAL_SECONDS), || {
+            Self::purge_deleted_groups();
+        });
+        set_timer_interval(Duration::from_secs(JOB_RETRY_INTERVAL_SECONDS), || {
+            ic_cdk::spawn(Jobs::run_jobs());
+        });
+        set_timer_interval(Duration::from_secs(GRANT_EXPIRY_INTERVAL_SECONDS), || {
+            RoleGrants::revoke_expired_grants();
+        });
+    }
+
+    pub fn get_deletion_policy() -> DeletionPolicy {
+        DELETION_POLICY.with(|policy| policy.borrow().get().clone())
+    }
+
+    pub fn set_deletion_policy(policy: DeletionPolicy) -> Result<DeletionPolicy, ApiError> {
+        if policy.restore_period_seconds > policy.retention_period_seconds {
+            return Err(api_error(
+                ApiErrorType::BadRequest,
+                "INVALID_DELETION_POLICY",
+                "The restore period can't be longer than the retention period",
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                "set_deletion_policy",
+                Some(vec![format!("policy - {:?}", &policy)]),
+            ));
+        }
+
+        if policy.retention_period_seconds > MAX_RETENTION_PERIOD_SECONDS {
+            return Err(api_error(
+                ApiErrorType::BadRequest,
+                "INVALID_DELETION_POLICY",
+                "The retention period is too long",
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                "set_deletion_policy",
+                Some(vec![
+                    format!("policy - {:?}", &policy),
+                    format!(
+                        "max_retention_period_seconds - {:?}",
+                        &MAX_RETENTION_PERIOD_SECONDS
+                    ),
+                ]),
+            ));
+        }
+
+        let _ = DELETION_POLICY.with(|cell| cell.borrow_mut().set(policy.clone()));
+        Ok(policy)
+    }
+
+    // Groups deleted before the deletion time was stored use the last update, which is set on deletion
+    fn get_deleted_on(group: &Group) -> u64 {
+        group.deleted_on.unwrap_or(group.updated_on)
+    }
+
+    fn is_restorable(group: &Group) -> bool {
+        let restore_period =
+            DELETION_POLICY.with(|policy| policy.borrow().get().restore_period_seconds);
+        Self::get_deleted_on(group).saturating_add(restore_period.saturating_mul(1_000_000_000))
+            >= time()
+    }
+
+    // Method to add a group that is passed along by the parent when this canister is spun up
+    // only the new group is added to the indexes, the other groups are already indexed
+    pub fn add_entry_by_parent(caller: Principal, entry: Vec<u8>) -> Result<(), ApiError> {
+        let entry_id = STABLE_DATA.with(|data| data.borrow().get().current_entry_id);
+        STABLE_DATA.with(|data| {
+            ENTRIES.with(|entries| {
+                Data::add_entry_by_parent(
+                    data,
+                    entries,
+                    caller,
+                    entry,
+                    Some(IDENTIFIER_KIND.to_string()),
+                )
             })
+        })?;
+
+        // The parent stores the group under the next entry id, same as `Data::add_entry_by_parent`
+        let identifier = Identifier::new(entry_id, api::id(), IDENTIFIER_KIND.to_string())
+            .and_then(|_identifier| _identifier.encode());
+        if let Ok(_identifier) = identifier {
+            let identifier = _identifier.to_string();
+            if let Some(_group_data) = ENTRIES.with(|entries| entries.borrow().get(&identifier)) {
+                Self::add_to_indexes(&identifier, &_group_data);
+            }
+        }
+        Ok(())
+    }
+
+    // Method to rebuild the indexes from the stored groups
+    // used when groups are written without going through the store (backup restore), or as a migration (see `rebuild_indexes` in methods.rs)
+    pub fn rebuild_indexes() {
+        let groups = ENTRIES.with(Data::get_entries);
+        SearchIndex::rebuild(&groups);
+        GroupIndex::rebuild(&groups);
+        GroupInvites::rebuild_index();
+    }
+
+    // Method to add a group to the search and filter indexes
+    fn add_to_indexes(identifier: &str, group: &Group) {
+        SearchIndex::add_group(identifier, group);
+        GroupIndex::add_group(identifier, group);
+    }
+
+    // Method to remove a group from the search and filter indexes, pass the group as it was indexed
+    fn remove_from_indexes(identifier: &str, group: &Group) {
+        SearchIndex::remove_group(identifier, group);
+        GroupIndex::remove_group(identifier, group);
+    }
+
+    // Method to write an updated group to the data store
+    // every change of an existing group goes through here so the indexes stay in sync and the change is added to the audit log
+    // the revision of the group is bumped, if an expected revision is passed the change is rejected when the group has a different revision
+    fn write_group(
+        data: &RefCell<StableCell<Data, Memory>>,
+        caller: Principal,
+        method_name: &str,
+        group_identifier: Principal,
+        previous_group_data: &Group,
+        mut group_data: Group,
+        expected_revision: Option<u64>,
+    ) -> Result<(Principal, Group), ApiError> {
+        let revision = previous_group_data.revision.unwrap_or(0);
+        if let Some(_expected_revision) = expected_revision {
+            if _expected_revision != revision {
+                return Err(api_error(
+                    ApiErrorType::BadRequest,
+                    "REVISION_CONFLICT",
+                    "The group was changed by someone else, reload the group and try again",
+                    Data::get_name(data.borrow().get()).as_str(),
+                    method_name,
+                    Some(vec![
+                        format!("group_identifier - {:?}", &group_identifier),
+                        format!("expected_revision - {:?}", &_expected_revision),
+                        format!("revision - {:?}", &revision),
+                    ]),
+                ));
+            }
+        }
+        group_data.revision = Some(revision + 1);
+
+        let update_group_result =
+            ENTRIES.with(|entries| Data::update_entry(data, entries, group_identifier, group_data));
+
+        match update_group_result {
+            Err(err) => Err(err),
+            Ok((_identifier, _group_data)) => {
+                let identifier = _identifier.to_string();
+                Self::remove_from_indexes(&identifier, previous_group_data);
+                Self::add_to_indexes(&identifier, &_group_data);
+                AuditLog::add_record(
+                    caller,
+                    method_name,
+                    _identifier,
+                    Some(previous_group_data),
+                    Some(&_group_data),
+                );
+                Ok((_identifier, _group_data))
+            }
+        }
+    }
+
+    // Method to get the groups that can match the filters
+    // the filter indexes are used when possible so only those groups are loaded instead of all the groups
+    fn get_filter_candidates(expression: &GroupFilterExpression) -> Vec<(String, Group)> {
+        match GroupIndex::get_candidates(expression) {
+            None => ENTRIES.with(Data::get_entries),
+            Some(identifiers) => ENTRIES.with(|entries| {
+                let entries = entries.borrow();
+                identifiers
+                    .into_iter()
+                    .filter_map(|identifier| {
+                        entries
+                            .get(&identifier)
+                            .map(|group_data| (identifier, group_data))
+                    })
+                    .collect()
+            }),
+        }
+    }
+
+    // Method to get a group with an identifier from the data store
+    pub fn get_group(identifier: Principal) -> Result<GroupResponse, ApiError> {
+        STABLE_DATA.with(|data| {
+            match ENTRIES.with(|entries| Data::get_entry(data, entries, identifier)) {
+                Err(err) => Err(err),
+                Ok((_identifier, _group_data)) => {
+                    if _group_data.is_deleted {
+                        return Err(api_error(
+                            ApiErrorType::NotFound,
+                            "GROUP_NOT_FOUND",
+                            "No group found",
+                            Data::get_name(data.borrow().get()).as_str(),
+                            "get_group",
+                            None,
+                        ));
+                    }
+
+                    Ok(Self::map_group_to_group_response(
+                        _identifier.to_string(),
+                        _group_data,
+                    ))
+                }
+            }
+        })
+    }
+
+    pub fn add_wallet(
+        caller: Principal,
+        group_identifier: Principal,
+        wallet_canister: Principal,
+        description: String,
+    ) -> Result<(), ApiError> {
+        STABLE_DATA.with(|data| {
+            match ENTRIES.with(|entries| Data::get_entry(data, entries, group_identifier)) {
+                Err(err) => Err(err),
+                Ok((_identifier, mut _group_data)) => {
+                    let previous_group_data = _group_data.clone();
+                    _group_data.wallets.insert(wallet_canister, description);
+                    _group_data.updated_on = time();
+
+                    match Self::write_group(
+                        data,
+                        caller,
+                        "add_wallet",
+                        _identifier,
+                        &previous_group_data,
+                        _group_data,
+                        None,
+                    ) {
+                        Err(err) => Err(err),
+                        Ok(_) => Ok(()),
+                    }
+                }
+            }
+        })
+    }
+
+    pub fn remove_wallet(
+        caller: Principal,
+        group_identifier: Principal,
+        wallet_canister: Principal,
+    ) -> Result<(), ApiError> {
+        STABLE_DATA.with(|data| {
+            // Check if the group exists in the data store
+            match ENTRIES.with(|entries| Data::get_entry(data, entries, group_identifier)) {
+                Err(err) => Err(err),
+                Ok((_identifier, mut _group_data)) => {
+                    let previous_group_data = _group_data.clone();
+                    _group_data.wallets.remove(&wallet_canister);
+                    _group_data.updated_on = time();
+
+                    match Self::write_group(
+                        data,
+                        caller,
+                        "remove_wallet",
+                        _identifier,
+                        &previous_group_data,
+                        _group_data,
+                        None,
+                    ) {
+                        Err(err) => Err(err),
+                        Ok(_) => Ok(()),
+                    }
+                }
+            }
+        })
+    }
+
+    // Method to get a group with an identifier from the data store
+    pub fn get_group_owner_and_privacy(
+        identifier: Principal,
+    ) -> Result<(Principal, Privacy), ApiError> {
+        STABLE_DATA.with(|data| {
+            match ENTRIES.with(|entries| Data::get_entry(data, entries, identifier)) {
+                Err(err) => Err(err),
+                Ok((_, _group_data)) => Ok((_group_data.owner, _group_data.privacy)),
+            }
+        })
+    }
+
+    // This method is used to get groups filtered and sorted with pagination
+    pub fn get_groups(
+        limit: usize,
+        page: usize,
+        filters: Vec<GroupFilter>,
+        filter_type: FilterType,
+        sort: GroupSort,
+        include_invite_only: bool,
+        filter_expression: Option<GroupFilterExpression>,
+    ) -> PagedResponse<GroupResponse> {
+        let expression = GroupFilterExpression::combine(filters, filter_type, filter_expression)
+            .with_privacy_policy(include_invite_only);
+        let groups = Self::get_filter_candidates(&expression);
+        // Get groups for filtering and sorting
+        let mapped_groups: Vec<GroupResponse> = groups
+            .iter()
+            // Filter out deleted groups
+            .filter(|(_identifier, _group_data)| !_group_data.is_deleted)
             // Map groups to group response
             .map(|(_identifier, _group_data)| {
                 Self::map_group_to_group_response(_identifier.clone(), _group_data.clone())
             })
             .collect();
 
+        // Get the search score per group, used when ordering on relevance
+        let searches = Self::get_searches(&expression);
+        let relevance = Self::get_search_relevance(&searches);
         // Filter groups
-        let filtered_groups = Self::get_filtered_groups(mapped_groups, filters, filter_type);
+        let filtered_groups = Self::get_filtered_groups(mapped_groups, &expression, &searches);
         // Order groups
-        let ordered_groups = Self::get_ordered_groups(filtered_groups, sort);
+        let ordered_groups = Self::get_ordered_groups(filtered_groups, sort, &relevance);
 
         // Paginate groups and return
         get_paged_data(ordered_groups, limit, page)
@@ -425,13 +1176,18 @@ impl Store {
     // Used for composite_query calls from the parent canister
     //
     // Method to get filtered groups serialized and chunked
+    // the privacy policy of the listing is already applied to the filter expression by the parent canister
+    // if a query is passed the groups are sorted and only the next `limit` groups after the cursor are returned as `SortedGroups`
     pub fn get_chunked_data(
         filters: Vec<GroupFilter>,
         filter_type: FilterType,
         chunk: usize,
         max_bytes_per_chunk: usize,
+        query: Option<GroupChunkQuery>,
+        filter_expression: Option<GroupFilterExpression>,
     ) -> (Vec<u8>, (usize, usize)) {
-        let groups = ENTRIES.with(|entries| Data::get_entries(entries));
+        let expression = GroupFilterExpression::combine(filters, filter_type, filter_expression);
+        let groups = Self::get_filter_candidates(&expression);
         // Get groups for filtering
         let mapped_groups: Vec<GroupResponse> = groups
             .iter()
@@ -443,8 +1199,19 @@ impl Store {
             })
             .collect();
 
-        let filtered_groups = Self::get_filtered_groups(mapped_groups, filters, filter_type);
-        if let Ok(bytes) = serialize(&filtered_groups) {
+        let searches = Self::get_searches(&expression);
+        let relevance = Self::get_search_relevance(&searches);
+        let filtered_groups = Self::get_filtered_groups(mapped_groups, &expression, &searches);
+        let serialized = match query {
+            None => serialize(&filtered_groups),
+            Some(_query) => serialize(&Self::get_sorted_groups(
+                filtered_groups,
+                _query,
+                &relevance,
+            )),
+        };
+
+        if let Ok(bytes) = serialized {
             // Check if the bytes of the serialized groups are greater than the max bytes per chunk specified as an argument
             if bytes.len() >= max_bytes_per_chunk {
                 // Get the start and end index of the bytes to be returned
@@ -504,12 +1271,14 @@ impl Store {
 
     // Method to add a custom role to the group
     pub fn add_role(
-        caller: Principal,
+        manager: RoleManager,
         group_identifier: Principal,
         role_name: String,
         color: String,
         index: u64,
+        expected_revision: Option<u64>,
     ) -> Result<GroupRole, ApiError> {
+        let caller = manager.principal;
         let inputs = Some(vec![
             format!("caller - {:?}", &caller.to_string()),
             format!("group_id - {:?}", &group_identifier),
@@ -521,18 +1290,19 @@ impl Store {
             match ENTRIES.with(|entries| Data::get_entry(data, entries, group_identifier)) {
                 Err(err) => Err(err),
                 Ok((_identifier, mut _group_data)) => {
-                    // check if the caller is the owner of the group
-                    if _group_data.owner != caller {
+                    // check if the new role is ranked below the highest role of the caller
+                    if !manager.can_manage(RoleHierarchy::get_custom_role_rank(Some(index))) {
                         return Err(api_error(
                             ApiErrorType::Unauthorized,
-                            "UNAUTHORIZED",
-                            "Only owner of a group can add roles",
+                            "INSUFFICIENT_ROLE_RANK",
+                            "You can only add roles ranked below your own role",
                             Data::get_name(data.borrow().get()).as_str(),
                             "add_role",
                             inputs,
                         ));
                     }
 
+                    let previous_group_data = _group_data.clone();
                     let mut roles = _group_data.roles;
                     let included_role = roles.iter().any(|r| r.name == role_name);
 
@@ -563,11 +1333,15 @@ impl Store {
                     _group_data.roles = roles;
                     _group_data.updated_on = time();
 
-                    let update_group_result = ENTRIES.with(|entries| {
-                        Data::update_entry(data, entries, group_identifier, _group_data.clone())
-                    });
-
-                    match update_group_result {
+                    match Self::write_group(
+                        data,
+                        caller,
+                        "add_role",
+                        group_identifier,
+                        &previous_group_data,
+                        _group_data,
+                        expected_revision,
+                    ) {
                         Err(err) => Err(err),
                         Ok(_) => Ok(new_role),
                     }
@@ -629,34 +1403,46 @@ impl Store {
         return vec![];
     }
 
-    // TODO: inter-canister call to remove role from members
     // Method to remove custom role from group
+    // the member canisters are notified through jobs so the role is removed from the members, or replaced by the fallback role
     pub fn remove_role(
-        caller: Principal,
+        manager: RoleManager,
         group_identifier: Principal,
         role_name: String,
+        fallback_role: Option<String>,
+        expected_revision: Option<u64>,
     ) -> Result<bool, ApiError> {
+        let caller = manager.principal;
         let inputs = Some(vec![
             format!("caller - {:?}", &caller.to_string()),
             format!("group_id - {:?}", &group_identifier),
             format!("role_name` - {:?}", &role_name),
+            format!("fallback_role - {:?}", &fallback_role),
         ]);
 
         STABLE_DATA.with(|data| {
             match ENTRIES.with(|entries| Data::get_entry(data, entries, group_identifier)) {
                 Err(err) => Err(err),
                 Ok((_identifier, mut _group_data)) => {
-                    // check if the caller is the owner of the group
-                    if _group_data.owner != caller {
+                    // check if the role and the fallback role are ranked below the highest role of the caller
+                    let role_rank = RoleHierarchy::get_role_rank(&_group_data.roles, &role_name);
+                    let fallback_rank = fallback_role
+                        .as_ref()
+                        .map(|r| RoleHierarchy::get_role_rank(&_group_data.roles, r));
+                    if !manager.can_manage(role_rank)
+                        || fallback_rank.is_some_and(|rank| !manager.can_manage(rank))
+                    {
                         return Err(api_error(
                             ApiErrorType::Unauthorized,
-                            "UNAUTHORIZED",
-                            "Only owner of a group can add roles",
+                            "INSUFFICIENT_ROLE_RANK",
+                            "You can only remove and assign roles ranked below your own role",
                             Data::get_name(data.borrow().get()).as_str(),
-                            "add_role",
+                            "remove_role",
                             inputs,
                         ));
                     }
+                    let previous_group_data = _group_data.clone();
+
                     // check if the role exists
                     let existing_role = _group_data.roles.iter().find(|r| r.name == role_name);
 
@@ -682,6 +1468,25 @@ impl Store {
                                 ));
                             };
 
+                            // the fallback role needs to be an other existing role, members cant become owner this way
+                            if let Some(_fallback_role) = &fallback_role {
+                                let is_valid_fallback = _fallback_role != &role_name
+                                    && _fallback_role != "owner"
+                                    && (_group_data.roles.iter().any(|r| &r.name == _fallback_role)
+                                        || default_roles().iter().any(|r| &r.name == _fallback_role));
+
+                                if !is_valid_fallback {
+                                    return Err(api_error(
+                                        ApiErrorType::BadRequest,
+                                        "INVALID_FALLBACK_ROLE",
+                                        "The fallback role needs to be an other existing role of the group",
+                                        Data::get_name(data.borrow().get()).as_str(),
+                                        "remove_role",
+                                        inputs,
+                                    ));
+                                }
+                            }
+
                             // remove the role to update from the existing roles
                             let updated_roles: Vec<GroupRole> = _group_data
                                 .roles
@@ -693,13 +1498,35 @@ impl Store {
                             _group_data.roles = updated_roles;
                             _group_data.updated_on = time();
 
-                            let update_group_result = ENTRIES.with(|entries| {
-                                Data::update_entry(data, entries, _identifier, _group_data)
-                            });
-
-                            match update_group_result {
+                            match Self::write_group(
+                                data,
+                                caller,
+                                "remove_role",
+                                _identifier,
+                                &previous_group_data,
+                                _group_data,
+                                expected_revision,
+                            ) {
                                 Err(err) => Err(err),
-                                Ok(_) => Ok(true),
+                                Ok((_identifier, _group_data)) => {
+                                    // temporary holders of the role get the fallback role until the grant expires
+                                    RoleGrants::move_grants(
+                                        _identifier,
+                                        &role_name,
+                                        fallback_role.as_deref(),
+                                    );
+                                    // invites for the role are removed, the fallback could be ranked higher than the creator can grant
+                                    GroupInvites::move_invites(_identifier, &role_name, None);
+                                    Jobs::add_jobs(
+                                        _identifier,
+                                        GroupJobKind::RoleRemoved {
+                                            role_name,
+                                            fallback_role,
+                                        },
+                                        _group_data.member_count.keys().cloned().collect(),
+                                    );
+                                    Ok(true)
+                                }
                             }
                         }
                     }
@@ -708,83 +1535,581 @@ impl Store {
         })
     }
 
-    // TODO: inter-canister call to remove role from members
-    // Method to update role permissions for custom roles
-    pub fn update_role_permissions(
-        caller: Principal,
+    // Method to assign a role to a member through the member canister, the role is revoked at `expires_on` if passed
+    // assigning a role without an expiry makes an earlier temporary assignment of the role permanent
+    // the assignee needs to be a member of the group and cant be the caller
+    pub async fn assign_role(
+        manager: RoleManager,
         group_identifier: Principal,
+        assignee_member_identifier: Principal,
         role_name: String,
-        post_permissions: Vec<PostPermission>,
-    ) -> Result<bool, ApiError> {
+        expires_on: Option<u64>,
+    ) -> Result<(), ApiError> {
+        let caller = manager.principal;
         let inputs = Some(vec![
             format!("caller - {:?}", &caller.to_string()),
-            format!("group_id - {:?}", &role_name),
+            format!("group_id - {:?}", &group_identifier),
+            format!(
+                "assignee_member_identifier - {:?}",
+                &assignee_member_identifier
+            ),
             format!("role_name - {:?}", &role_name),
-            format!("permissions - {:?}", &post_permissions),
+            format!("expires_on - {:?}", &expires_on),
         ]);
 
+        // the membership is checked with the member canister directly, the cached roles could be outdated
+        match get_member_roles(assignee_member_identifier, group_identifier).await {
+            Err(err) => {
+                return Err(api_error(
+                    ApiErrorType::NotFound,
+                    "MEMBER_NOT_FOUND",
+                    err.as_str(),
+                    STABLE_DATA
+                        .with(|data| Data::get_name(data.borrow().get()))
+                        .as_str(),
+                    "assign_role",
+                    inputs,
+                ))
+            }
+            Ok((_principal, _)) if _principal == caller => {
+                return Err(api_error(
+                    ApiErrorType::BadRequest,
+                    "SELF_ASSIGNMENT",
+                    "You cant assign a role to yourself",
+                    STABLE_DATA
+                        .with(|data| Data::get_name(data.borrow().get()))
+                        .as_str(),
+                    "assign_role",
+                    inputs,
+                ))
+            }
+            Ok(_) => {}
+        }
+
         STABLE_DATA.with(|data| {
             match ENTRIES.with(|entries| Data::get_entry(data, entries, group_identifier)) {
                 Err(err) => Err(err),
-                Ok((_identifier, mut _group_data)) => {
-                    // check if the caller is the owner of the group
-                    if _group_data.owner != caller {
+                Ok((_identifier, _group_data)) => {
+                    // the owner role can only be transferred
+                    if role_name == "owner" {
                         return Err(api_error(
-                            ApiErrorType::Unauthorized,
-                            "UNAUTHORIZED",
-                            "Only owner of a group can add roles",
+                            ApiErrorType::BadRequest,
+                            "PROTECTED_ROLE",
+                            "The owner role cant be assigned",
                             Data::get_name(data.borrow().get()).as_str(),
-                            "add_role",
+                            "assign_role",
                             inputs,
                         ));
                     }
 
-                    // check if the role exists
-                    let existing_role = _group_data.roles.iter().find(|r| r.name == role_name);
-                    match existing_role {
-                        None => Err(api_error(
+                    let is_existing_role = _group_data.roles.iter().any(|r| r.name == role_name)
+                        || default_roles().iter().any(|r| r.name == role_name);
+                    if !is_existing_role {
+                        return Err(api_error(
                             ApiErrorType::NotFound,
                             "ROLE_NOT_FOUND",
                             "The role cant be found for this group",
                             Data::get_name(data.borrow().get()).as_str(),
-                            "remove_role",
+                            "assign_role",
                             inputs,
-                        )),
-                        Some(_role) => {
-                            let mut permissions: Vec<Permission> = vec![];
-                            let required_permissions = get_read_only_permissions();
+                        ));
+                    }
 
-                            // iterate over the permissions passed as an argument
-                            post_permissions.iter().for_each(|p| {
-                                let required_permission =
-                                    required_permissions.iter().find(|r| r.name == p.name);
+                    // check if the role is ranked below the highest role of the caller
+                    if !manager
+                        .can_manage(RoleHierarchy::get_role_rank(&_group_data.roles, &role_name))
+                    {
+                        return Err(api_error(
+                            ApiErrorType::Unauthorized,
+                            "INSUFFICIENT_ROLE_RANK",
+                            "You can only assign roles ranked below your own role",
+                            Data::get_name(data.borrow().get()).as_str(),
+                            "assign_role",
+                            inputs,
+                        ));
+                    }
 
-                                // check if the permission is a required permission
-                                match required_permission {
-                                    None => permissions.push(Permission {
-                                        name: p.name.clone(),
-                                        protected: false,
-                                        actions: p.actions.clone(),
-                                    }),
-                                    Some(_permission) => {
-                                        // if the permission is protected set the actions as default
-                                        if _permission.protected {
-                                            permissions.push(Permission {
-                                                name: _permission.name.clone(),
-                                                protected: _permission.protected,
-                                                actions: _permission.actions.clone(),
-                                            })
-                                        } else {
-                                            permissions.push(Permission {
-                                                name: _permission.name.clone(),
-                                                protected: _permission.protected,
-                                                actions: p.actions.clone(),
-                                            })
-                                        }
-                                    }
+                    // a manager can only hand out the permissions it holds itself
+                    let mut group_roles = _group_data.roles.clone();
+                    group_roles.append(&mut default_roles());
+                    let role_permissions = group_roles
+                        .iter()
+                        .find(|r| r.name == role_name)
+                        .map(|r| r.permissions.clone())
+                        .unwrap_or_default();
+                    if !role_permissions
+                        .iter()
+                        .all(|p| manager.can_grant(&group_roles, p))
+                    {
+                        return Err(api_error(
+                            ApiErrorType::Unauthorized,
+                            "PERMISSION_NOT_HELD",
+                            "You cant assign a role with permissions you dont hold",
+                            Data::get_name(data.borrow().get()).as_str(),
+                            "assign_role",
+                            inputs,
+                        ));
+                    }
+
+                    match expires_on {
+                        Some(_expires_on) => {
+                            if _expires_on <= time() {
+                                return Err(api_error(
+                                    ApiErrorType::BadRequest,
+                                    "INVALID_EXPIRY",
+                                    "The expiry needs to be in the future",
+                                    Data::get_name(data.borrow().get()).as_str(),
+                                    "assign_role",
+                                    inputs,
+                                ));
+                            }
+
+                            RoleGrants::add_grant(RoleGrant {
+                                group_identifier: _identifier,
+                                member_identifier: assignee_member_identifier,
+                                role_name: role_name.clone(),
+                                granted_by: caller,
+                                expires_on: _expires_on,
+                                created_on: time(),
+                            });
+                        }
+                        None => RoleGrants::remove_grant(
+                            _identifier,
+                            assignee_member_identifier,
+                            &role_name,
+                        ),
+                    }
+
+                    Jobs::add_jobs(
+                        _identifier,
+                        GroupJobKind::RoleAssigned {
+                            member_identifier: assignee_member_identifier,
+                            role_name,
+                        },
+                        vec![RoleGrants::get_member_canister(assignee_member_identifier)],
+                    );
+                    Ok(())
+                }
+            }
+        })
+    }
+
+    // Method to create an invite code for an invite only or private group
+    // the role that is granted on join needs to be ranked below the highest role of the caller, the member role is used if none is passed
+    pub async fn create_invite(
+        manager: RoleManager,
+        group_identifier: Principal,
+        role_name: Option<String>,
+        max_uses: Option<u64>,
+        expires_on: Option<u64>,
+    ) -> Result<GroupInvite, ApiError> {
+        let caller = manager.principal;
+        let inputs = Some(vec![
+            format!("caller - {:?}", &caller.to_string()),
+            format!("group_id - {:?}", &group_identifier),
+            format!("role_name - {:?}", &role_name),
+            format!("max_uses - {:?}", &max_uses),
+            format!("expires_on - {:?}", &expires_on),
+        ]);
+        let get_error = |error_type: ApiErrorType, tag: &str, message: &str| {
+            api_error(
+                error_type,
+                tag,
+                message,
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                "create_invite",
+                inputs.clone(),
+            )
+        };
+
+        let (_identifier, _group_data) = STABLE_DATA.with(|data| {
+            ENTRIES.with(|entries| Data::get_entry(data, entries, group_identifier))
+        })?;
+
+        if _group_data.is_deleted {
+            return Err(get_error(
+                ApiErrorType::BadRequest,
+                "DELETED_GROUP",
+                "You cant create invites for a deleted group",
+            ));
+        }
+
+        if !Self::is_invitable(&_group_data) {
+            return Err(get_error(
+                ApiErrorType::BadRequest,
+                "INVALID_PRIVACY",
+                "Invites can only be created for invite only and private groups",
+            ));
+        }
+
+        if let Some(_role_name) = &role_name {
+            if _role_name == "owner" {
+                return Err(get_error(
+                    ApiErrorType::BadRequest,
+                    "PROTECTED_ROLE",
+                    "The owner role cant be granted through an invite",
+                ));
+            }
+
+            let is_existing_role = _group_data.roles.iter().any(|r| &r.name == _role_name)
+                || default_roles().iter().any(|r| &r.name == _role_name);
+            if !is_existing_role {
+                return Err(get_error(
+                    ApiErrorType::NotFound,
+                    "ROLE_NOT_FOUND",
+                    "The role cant be found for this group",
+                ));
+            }
+
+            if !manager.can_manage(RoleHierarchy::get_role_rank(&_group_data.roles, _role_name)) {
+                return Err(get_error(
+                    ApiErrorType::Unauthorized,
+                    "INSUFFICIENT_ROLE_RANK",
+                    "You can only grant roles ranked below your own role",
+                ));
+            }
+        }
+
+        if max_uses == Some(0) {
+            return Err(get_error(
+                ApiErrorType::BadRequest,
+                "INVALID_MAX_USES",
+                "The max uses need to be at least 1",
+            ));
+        }
+
+        if expires_on.is_some_and(|_expires_on| _expires_on <= time()) {
+            return Err(get_error(
+                ApiErrorType::BadRequest,
+                "INVALID_EXPIRY",
+                "The expiry needs to be in the future",
+            ));
+        }
+
+        let code = GroupInvites::generate_code()
+            .await
+            .map_err(|err| get_error(ApiErrorType::BadRequest, "CODE_GENERATION_FAILED", &err))?;
+
+        let invite = GroupInvite {
+            code,
+            group_identifier: _identifier,
+            role_name,
+            max_uses,
+            uses: 0,
+            expires_on,
+            created_by: caller,
+            created_by_rank: Some(manager.rank),
+            created_on: time(),
+        };
+        GroupInvites::add_invite(invite.clone());
+        Ok(invite)
+    }
+
+    // Method to get the invites of a group that are not expired or used up
+    pub fn get_group_invites(group_identifier: Principal) -> Vec<GroupInvite> {
+        GroupInvites::get_active_invites(group_identifier)
+    }
+
+    // Method to revoke an invite code, the join grants that are already issued stay valid until they expire
+    pub fn revoke_invite(
+        caller: Principal,
+        group_identifier: Principal,
+        code: String,
+    ) -> Result<(), ApiError> {
+        match GroupInvites::get_invite(&code) {
+            Some(_invite) if _invite.group_identifier == group_identifier => {
+                GroupInvites::remove_invite(&code);
+                Ok(())
+            }
+            _ => Err(api_error(
+                ApiErrorType::NotFound,
+                "INVITE_NOT_FOUND",
+                "The invite cant be found for this group",
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                "revoke_invite",
+                Some(vec![
+                    format!("caller - {:?}", &caller),
+                    format!("group_identifier - {:?}", &group_identifier),
+                    format!("code - {:?}", &code),
+                ]),
+            )),
+        }
+    }
+
+    // Method to redeem an invite code, returns a join grant for the caller that is accepted by the member canister
+    pub async fn redeem_invite(caller: Principal, code: String) -> Result<JoinGrant, ApiError> {
+        let inputs = Some(vec![
+            format!("caller - {:?}", &caller),
+            format!("code - {:?}", &code),
+        ]);
+        let get_error = |error_type: ApiErrorType, tag: &str, message: &str| {
+            api_error(
+                error_type,
+                tag,
+                message,
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                "redeem_invite",
+                inputs.clone(),
+            )
+        };
+
+        let secret = GroupInvites::get_secret()
+            .await
+            .map_err(|err| get_error(ApiErrorType::BadRequest, "SECRET_GENERATION_FAILED", &err))?;
+
+        // the invite is read after the await so the uses cant be exceeded by concurrent calls
+        let invite = match GroupInvites::get_invite(&code) {
+            Some(_invite) if GroupInvites::is_active(&_invite) => _invite,
+            _ => {
+                return Err(get_error(
+                    ApiErrorType::NotFound,
+                    "INVITE_NOT_FOUND",
+                    "The invite doesnt exist, is expired or is used up",
+                ))
+            }
+        };
+
+        let (_, _group_data) = STABLE_DATA.with(|data| {
+            ENTRIES.with(|entries| Data::get_entry(data, entries, invite.group_identifier))
+        })?;
+
+        if _group_data.is_deleted || !Self::is_invitable(&_group_data) {
+            return Err(get_error(
+                ApiErrorType::BadRequest,
+                "INVALID_GROUP",
+                "The group is deleted or cant be joined through an invite",
+            ));
+        }
+
+        // the role could be removed or moved above the creator after the invite was created
+        if let Some(_role_name) = &invite.role_name {
+            let is_existing_role = _group_data.roles.iter().any(|r| &r.name == _role_name)
+                || default_roles().iter().any(|r| &r.name == _role_name);
+            let role_rank = RoleHierarchy::get_role_rank(&_group_data.roles, _role_name);
+            let is_grantable = invite
+                .created_by_rank
+                .is_some_and(|_created_by_rank| role_rank > _created_by_rank);
+
+            if _role_name == "owner" || !is_existing_role || !is_grantable {
+                return Err(get_error(
+                    ApiErrorType::BadRequest,
+                    "INVALID_INVITE_ROLE",
+                    "The role of the invite doesnt exist anymore or cant be granted by the creator of the invite",
+                ));
+            }
+        }
+
+        Ok(GroupInvites::issue_join_grant(&secret, invite, caller))
+    }
+
+    // Method for the member canister to check if a join grant is issued by this canister and not expired
+    // grants for groups that are deleted after the grant was issued are rejected
+    pub fn verify_join_grant(join_grant: JoinGrant) -> bool {
+        let is_active_group = STABLE_DATA
+            .with(|data| {
+                ENTRIES.with(|entries| Data::get_entry(data, entries, join_grant.group_identifier))
+            })
+            .is_ok_and(|(_, _group_data)| !_group_data.is_deleted);
+
+        is_active_group && GroupInvites::verify_join_grant(&join_grant)
+    }
+
+    // Invites skip the gates of a gated group, so only invite only and private groups can use them
+    fn is_invitable(group: &Group) -> bool {
+        matches!(group.privacy, Privacy::InviteOnly | Privacy::Private)
+    }
+
+    // Method for the member canister to remove the cached roles of a member after its roles changed
+    pub fn invalidate_member_roles(
+        caller: Principal,
+        member_identifier: Principal,
+        group_identifier: Principal,
+    ) -> Result<(), ApiError> {
+        if caller != RoleGrants::get_member_canister(member_identifier) {
+            return Err(api_error(
+                ApiErrorType::Unauthorized,
+                "UNAUTHORIZED",
+                "Only the member canister of the member can invalidate its roles",
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                "invalidate_member_roles",
+                Some(vec![
+                    format!("caller - {:?}", &caller),
+                    format!("member_identifier - {:?}", &member_identifier),
+                    format!("group_identifier - {:?}", &group_identifier),
+                ]),
+            ));
+        }
+
+        MemberRoleCache::invalidate(member_identifier, group_identifier);
+        Ok(())
+    }
+
+    // Method to fetch the roles of a member from the member canister, the cached roles are replaced
+    pub async fn refresh_member_roles(
+        caller: Principal,
+        group_identifier: Principal,
+        member_identifier: Principal,
+    ) -> Result<Vec<String>, ApiError> {
+        let inputs = Some(vec![
+            format!("caller - {:?}", &caller),
+            format!("group_identifier - {:?}", &group_identifier),
+            format!("member_identifier - {:?}", &member_identifier),
+        ]);
+
+        match MemberRoleCache::refresh(member_identifier, group_identifier).await {
+            Ok((_principal, _roles)) => {
+                if caller != _principal {
+                    return Err(api_error(
+                        ApiErrorType::Unauthorized,
+                        "PRINCIPAL_MISMATCH",
+                        "Principal mismatch",
+                        STABLE_DATA
+                            .with(|data| Data::get_name(data.borrow().get()))
+                            .as_str(),
+                        "refresh_member_roles",
+                        inputs,
+                    ));
+                }
+                Ok(_roles)
+            }
+            Err(err) => Err(api_error(
+                ApiErrorType::NotFound,
+                "MEMBER_NOT_FOUND",
+                err.as_str(),
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                "refresh_member_roles",
+                inputs,
+            )),
+        }
+    }
+
+    // Method to get the temporary roles of a group that are not revoked yet
+    pub fn get_role_grants(group_identifier: Principal) -> Vec<RoleGrant> {
+        RoleGrants::get_grants(group_identifier)
+    }
+
+    // Method to get the rank of every role of a group, highest role first (a lower rank is a higher role)
+    pub fn get_role_ranks(group_identifier: Principal) -> Vec<(String, u64)> {
+        let group = STABLE_DATA
+            .with(|data| ENTRIES.with(|entries| Data::get_entry(data, entries, group_identifier)));
+        match group {
+            Ok((_, _group)) => RoleHierarchy::get_role_ranks(&_group.roles),
+            Err(_) => vec![],
+        }
+    }
+
+    // Method to update role permissions for custom roles
+    pub fn update_role_permissions(
+        manager: RoleManager,
+        group_identifier: Principal,
+        role_name: String,
+        post_permissions: Vec<PostPermission>,
+        expected_revision: Option<u64>,
+    ) -> Result<bool, ApiError> {
+        let caller = manager.principal;
+        let inputs = Some(vec![
+            format!("caller - {:?}", &caller.to_string()),
+            format!("group_id - {:?}", &role_name),
+            format!("role_name - {:?}", &role_name),
+            format!("permissions - {:?}", &post_permissions),
+        ]);
+
+        STABLE_DATA.with(|data| {
+            match ENTRIES.with(|entries| Data::get_entry(data, entries, group_identifier)) {
+                Err(err) => Err(err),
+                Ok((_identifier, mut _group_data)) => {
+                    // check if the role is ranked below the highest role of the caller
+                    if !manager
+                        .can_manage(RoleHierarchy::get_role_rank(&_group_data.roles, &role_name))
+                    {
+                        return Err(api_error(
+                            ApiErrorType::Unauthorized,
+                            "INSUFFICIENT_ROLE_RANK",
+                            "You can only edit roles ranked below your own role",
+                            Data::get_name(data.borrow().get()).as_str(),
+                            "update_role_permissions",
+                            inputs,
+                        ));
+                    }
+
+                    let previous_group_data = _group_data.clone();
+
+                    // check if the role exists
+                    let existing_role = _group_data.roles.iter().find(|r| r.name == role_name);
+                    match existing_role {
+                        None => Err(api_error(
+                            ApiErrorType::NotFound,
+                            "ROLE_NOT_FOUND",
+                            "The role cant be found for this group",
+                            Data::get_name(data.borrow().get()).as_str(),
+                            "update_role_permissions",
+                            inputs,
+                        )),
+                        Some(_role) => {
+                            let mut permissions: Vec<Permission> = vec![];
+                            let required_permissions = get_read_only_permissions();
+
+                            // iterate over the permissions passed as an argument
+                            post_permissions.iter().for_each(|p| {
+                                let required_permission =
+                                    required_permissions.iter().find(|r| r.name == p.name);
+
+                                // check if the permission is a required permission
+                                match required_permission {
+                                    None => permissions.push(Permission {
+                                        name: p.name.clone(),
+                                        protected: false,
+                                        actions: p.actions.clone(),
+                                    }),
+                                    Some(_permission) => {
+                                        // if the permission is protected set the actions as default
+                                        if _permission.protected {
+                                            permissions.push(Permission {
+                                                name: _permission.name.clone(),
+                                                protected: _permission.protected,
+                                                actions: _permission.actions.clone(),
+                                            })
+                                        } else {
+                                            permissions.push(Permission {
+                                                name: _permission.name.clone(),
+                                                protected: _permission.protected,
+                                                actions: p.actions.clone(),
+                                            })
+                                        }
+                                    }
                                 }
                             });
 
+                            // a manager can only hand out the permissions it holds itself
+                            let mut group_roles = _group_data.roles.clone();
+                            group_roles.append(&mut default_roles());
+                            if let Some(_permission) =
+                                permissions.iter().find(|p| !manager.can_grant(&group_roles, p))
+                            {
+                                return Err(api_error(
+                                    ApiErrorType::Unauthorized,
+                                    "PERMISSION_NOT_HELD",
+                                    format!(
+                                        "You cant grant the `{}` permission, you dont hold all of its actions",
+                                        _permission.name
+                                    )
+                                    .as_str(),
+                                    Data::get_name(data.borrow().get()).as_str(),
+                                    "update_role_permissions",
+                                    inputs,
+                                ));
+                            }
+
                             let updated_role = GroupRole {
                                 name: _role.name.clone(),
                                 protected: _role.protected,
@@ -801,25 +2126,270 @@ impl Store {
                                 .cloned()
                                 .collect();
 
-                            roles.push(updated_role);
+                            roles.push(updated_role);
+
+                            _group_data.roles = roles;
+                            _group_data.updated_on = time();
+
+                            match Self::write_group(
+                                data,
+                                caller,
+                                "update_role_permissions",
+                                _identifier,
+                                &previous_group_data,
+                                _group_data,
+                                expected_revision,
+                            ) {
+                                Err(err) => Err(err),
+                                Ok(_) => Ok(true),
+                            }
+                        }
+                    }
+                }
+            }
+        })
+    }
+    // Method to change the name, color and index of a custom role, the permissions of the role are kept
+    // the member canisters are notified of a new name through jobs so the members keep the role
+    pub fn edit_role(
+        manager: RoleManager,
+        group_identifier: Principal,
+        role_name: String,
+        new_name: String,
+        color: String,
+        index: u64,
+        expected_revision: Option<u64>,
+    ) -> Result<GroupRole, ApiError> {
+        let caller = manager.principal;
+        let inputs = Some(vec![
+            format!("caller - {:?}", &caller.to_string()),
+            format!("group_id - {:?}", &group_identifier),
+            format!("role_name - {:?}", &role_name),
+            format!("new_name - {:?}", &new_name),
+            format!("color - {:?}", &color),
+            format!("index - {:?}", &index),
+        ]);
+
+        STABLE_DATA.with(|data| {
+            match ENTRIES.with(|entries| Data::get_entry(data, entries, group_identifier)) {
+                Err(err) => Err(err),
+                Ok((_identifier, mut _group_data)) => {
+                    // check if the role is ranked below the highest role of the caller, before and after the edit
+                    let role_rank = RoleHierarchy::get_role_rank(&_group_data.roles, &role_name);
+                    let new_role_rank = RoleHierarchy::get_custom_role_rank(Some(index));
+                    if !manager.can_manage(role_rank) || !manager.can_manage(new_role_rank) {
+                        return Err(api_error(
+                            ApiErrorType::Unauthorized,
+                            "INSUFFICIENT_ROLE_RANK",
+                            "You can only edit roles ranked below your own role",
+                            Data::get_name(data.borrow().get()).as_str(),
+                            "edit_role",
+                            inputs,
+                        ));
+                    }
+
+                    let previous_group_data = _group_data.clone();
+
+                    // check if the role exists, default roles are not part of the group roles so they cant be edited
+                    let existing_role = _group_data.roles.iter().find(|r| r.name == role_name);
+
+                    match existing_role {
+                        None => Err(api_error(
+                            ApiErrorType::NotFound,
+                            "ROLE_NOT_FOUND",
+                            "The role cant be found for this group",
+                            Data::get_name(data.borrow().get()).as_str(),
+                            "edit_role",
+                            inputs,
+                        )),
+                        Some(_role) => {
+                            // check if the new name is already used by another custom or default role
+                            let included_role = _group_data
+                                .roles
+                                .iter()
+                                .any(|r| r.name == new_name && r.name != role_name);
+
+                            if included_role || default_roles().iter().any(|r| r.name == new_name) {
+                                return Err(api_error(
+                                    ApiErrorType::BadRequest,
+                                    "EXISTING_ROLE",
+                                    "This role is already registered",
+                                    Data::get_name(data.borrow().get()).as_str(),
+                                    "edit_role",
+                                    inputs,
+                                ));
+                            };
+
+                            let updated_role = GroupRole {
+                                name: new_name.clone(),
+                                protected: _role.protected,
+                                permissions: _role.permissions.clone(),
+                                color,
+                                index: Some(index),
+                            };
+
+                            _group_data.roles = _group_data
+                                .roles
+                                .iter()
+                                .map(|r| {
+                                    if r.name == role_name {
+                                        updated_role.clone()
+                                    } else {
+                                        r.clone()
+                                    }
+                                })
+                                .collect();
+                            _group_data.updated_on = time();
+
+                            match Self::write_group(
+                                data,
+                                caller,
+                                "edit_role",
+                                _identifier,
+                                &previous_group_data,
+                                _group_data,
+                                expected_revision,
+                            ) {
+                                Err(err) => Err(err),
+                                Ok((_identifier, _group_data)) => {
+                                    if role_name != new_name {
+                                        RoleGrants::move_grants(
+                                            _identifier,
+                                            &role_name,
+                                            Some(&new_name),
+                                        );
+                                        GroupInvites::move_invites(
+                                            _identifier,
+                                            &role_name,
+                                            Some(&new_name),
+                                        );
+                                        Jobs::add_jobs(
+                                            _identifier,
+                                            GroupJobKind::RoleRenamed {
+                                                old_name: role_name,
+                                                new_name,
+                                            },
+                                            _group_data.member_count.keys().cloned().collect(),
+                                        );
+                                    }
+                                    Ok(updated_role)
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        })
+    }
+
+    // Method to set the order of the custom roles, the index of a role is its position in the passed names
+    // every custom role needs to be passed exactly once, default roles cant be reordered
+    pub fn reorder_roles(
+        manager: RoleManager,
+        group_identifier: Principal,
+        role_names: Vec<String>,
+        expected_revision: Option<u64>,
+    ) -> Result<Vec<GroupRole>, ApiError> {
+        let caller = manager.principal;
+        let inputs = Some(vec![
+            format!("caller - {:?}", &caller.to_string()),
+            format!("group_id - {:?}", &group_identifier),
+            format!("role_names - {:?}", &role_names),
+        ]);
+
+        STABLE_DATA.with(|data| {
+            match ENTRIES.with(|entries| Data::get_entry(data, entries, group_identifier)) {
+                Err(err) => Err(err),
+                Ok((_identifier, mut _group_data)) => {
+                    // check if every custom role is ranked below the highest role of the caller, before and after the reorder
+                    let can_reorder =
+                        _group_data
+                            .roles
+                            .iter()
+                            .map(|r| RoleHierarchy::get_custom_role_rank(r.index))
+                            .chain((0..role_names.len()).map(|index| {
+                                RoleHierarchy::get_custom_role_rank(Some(index as u64))
+                            }))
+                            .all(|rank| manager.can_manage(rank));
+                    if !can_reorder {
+                        return Err(api_error(
+                            ApiErrorType::Unauthorized,
+                            "INSUFFICIENT_ROLE_RANK",
+                            "You can only reorder roles ranked below your own role",
+                            Data::get_name(data.borrow().get()).as_str(),
+                            "reorder_roles",
+                            inputs,
+                        ));
+                    }
+
+                    if role_names
+                        .iter()
+                        .any(|name| default_roles().iter().any(|r| &r.name == name))
+                    {
+                        return Err(api_error(
+                            ApiErrorType::BadRequest,
+                            "PROTECTED_ROLE",
+                            "Default roles cant be reordered",
+                            Data::get_name(data.borrow().get()).as_str(),
+                            "reorder_roles",
+                            inputs,
+                        ));
+                    }
+
+                    // check if every custom role is passed exactly once
+                    let mut unique_names = role_names.clone();
+                    unique_names.sort();
+                    unique_names.dedup();
+                    let is_valid_order = unique_names.len() == role_names.len()
+                        && role_names.len() == _group_data.roles.len()
+                        && _group_data
+                            .roles
+                            .iter()
+                            .all(|r| role_names.contains(&r.name));
+
+                    if !is_valid_order {
+                        return Err(api_error(
+                            ApiErrorType::BadRequest,
+                            "INVALID_ROLE_ORDER",
+                            "Every custom role of the group needs to be passed exactly once",
+                            Data::get_name(data.borrow().get()).as_str(),
+                            "reorder_roles",
+                            inputs,
+                        ));
+                    }
+
+                    let previous_group_data = _group_data.clone();
 
-                            _group_data.roles = roles;
-                            _group_data.updated_on = time();
+                    let mut roles: Vec<GroupRole> = vec![];
+                    for (index, role_name) in role_names.iter().enumerate() {
+                        if let Some(_role) = _group_data.roles.iter().find(|r| &r.name == role_name)
+                        {
+                            let mut role = _role.clone();
+                            role.index = Some(index as u64);
+                            roles.push(role);
+                        }
+                    }
 
-                            let update_group_result = ENTRIES.with(|entries| {
-                                Data::update_entry(data, entries, _identifier, _group_data)
-                            });
+                    _group_data.roles = roles;
+                    _group_data.updated_on = time();
 
-                            match update_group_result {
-                                Err(err) => Err(err),
-                                Ok(_) => Ok(true),
-                            }
-                        }
+                    match Self::write_group(
+                        data,
+                        caller,
+                        "reorder_roles",
+                        _identifier,
+                        &previous_group_data,
+                        _group_data,
+                        expected_revision,
+                    ) {
+                        Err(err) => Err(err),
+                        Ok((_, _group_data)) => Ok(_group_data.roles),
                     }
                 }
             }
         })
     }
+
     async fn validate_group_privacy(
         caller: Principal,
         account_identifier: Option<String>,
@@ -902,45 +2472,139 @@ impl Store {
         }
     }
 
+    // Method to check which gates of a group the caller passes, all requirements are checked so the reason can be shown when the caller cant join
+    pub async fn check_join_eligibility(
+        caller: Principal,
+        group_identifier: Principal,
+        account_identifier: Option<String>,
+    ) -> Result<JoinEligibility, ApiError> {
+        let (_identifier, _group_data) = STABLE_DATA.with(|data| {
+            ENTRIES.with(|entries| Data::get_entry(data, entries, group_identifier))
+        })?;
+
+        if _group_data.is_deleted {
+            return Err(api_error(
+                ApiErrorType::NotFound,
+                "GROUP_DELETED",
+                "The group is deleted",
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                "check_join_eligibility",
+                Some(vec![
+                    format!("caller - {:?}", &caller),
+                    format!("group_identifier - {:?}", &group_identifier),
+                ]),
+            ));
+        }
+
+        let mut requirements: Vec<JoinRequirement> = vec![];
+        if let Privacy::Gated(gated_type) = &_group_data.privacy {
+            match gated_type {
+                GatedType::Neuron(neuron_canisters) => {
+                    for neuron_canister in neuron_canisters {
+                        requirements.push(JoinRequirement::Neuron(
+                            Self::get_neuron_requirement(
+                                caller,
+                                neuron_canister.governance_canister,
+                                neuron_canister.rules.clone(),
+                            )
+                            .await,
+                        ));
+                    }
+                }
+                GatedType::Token(nft_canisters) => {
+                    for nft_canister in nft_canisters {
+                        requirements.push(JoinRequirement::Token(
+                            Self::get_token_requirement(
+                                caller,
+                                account_identifier.clone(),
+                                nft_canister,
+                            )
+                            .await,
+                        ));
+                    }
+                }
+            }
+        }
+
+        if Self::is_invitable(&_group_data) {
+            requirements.push(JoinRequirement::Invite);
+        }
+
+        let required_amount = match _group_data.privacy {
+            Privacy::Gated(_) => _group_data.privacy_gated_type_amount.unwrap_or_default(),
+            Privacy::Private | Privacy::InviteOnly => 1,
+            Privacy::Public => 0,
+        };
+        let passed_amount = requirements
+            .iter()
+            .filter(|requirement| match requirement {
+                JoinRequirement::Token(_requirement) => _requirement.is_met,
+                JoinRequirement::Neuron(_requirement) => _requirement.is_met,
+                JoinRequirement::Invite => false,
+            })
+            .count() as u64;
+
+        Ok(JoinEligibility {
+            group_identifier: _identifier,
+            privacy: _group_data.privacy,
+            is_eligible: passed_amount >= required_amount,
+            required_amount,
+            passed_amount,
+            requirements,
+        })
+    }
+
     // Method to check if the caller owns a specific NFT
     pub async fn validate_nft_gated(
         principal: Principal,
         account_identifier: Option<String>,
         nft_canister: &TokenGated,
     ) -> bool {
+        Self::get_token_requirement(principal, account_identifier, nft_canister)
+            .await
+            .is_met
+    }
+
+    // Method to get the balance of the caller on a token or NFT canister
+    async fn get_token_requirement(
+        principal: Principal,
+        account_identifier: Option<String>,
+        nft_canister: &TokenGated,
+    ) -> TokenRequirement {
         // Check if the canister is a EXT, DIP20 or DIP721 canister
-        match nft_canister.standard.as_str() {
+        let balance: Result<u128, String> = match nft_canister.standard.as_str() {
             // If the canister is a EXT canister, check if the caller owns the NFT
             // This call uses the account_identifier
             "EXT" => match account_identifier {
                 Some(_account_identifier) => {
-                    let response =
-                        ext_balance_of(nft_canister.principal, _account_identifier).await;
-                    response as u64 >= nft_canister.amount
+                    Ok(ext_balance_of(nft_canister.principal, _account_identifier).await as u128)
                 }
-                None => false,
+                None => Err("An account identifier is needed for EXT canisters".to_string()),
             },
             // If the canister is a DIP20 canister, check if the caller owns the NFT
-            "DIP20" => {
-                let response = dip20_balance_of(nft_canister.principal, principal).await;
-                response as u64 >= nft_canister.amount
-            }
+            "DIP20" => Ok(dip20_balance_of(nft_canister.principal, principal).await as u128),
             // If the canister is a DIP721 canister, check if the caller owns the NFT
-            "DIP721" => {
-                let response = dip721_balance_of(nft_canister.principal, principal).await;
-                response as u64 >= nft_canister.amount
-            }
+            "DIP721" => Ok(dip721_balance_of(nft_canister.principal, principal).await as u128),
             // If the canister is a LEGACY DIP721 canister, check if the caller owns the NFT
             "DIP721_LEGACY" => {
-                let response = legacy_dip721_balance_of(nft_canister.principal, principal).await;
-                response as u64 >= nft_canister.amount
+                Ok(legacy_dip721_balance_of(nft_canister.principal, principal).await as u128)
             }
             // If the canister is a ICRC canister, check if the caller owns the amount of tokens
-            "ICRC" => {
-                let response = Self::icrc_balance_of(nft_canister.principal, principal).await;
-                response >= nft_canister.amount as u128
-            }
-            _ => false,
+            "ICRC" => Ok(Self::icrc_balance_of(nft_canister.principal, principal).await),
+            _ => Err(format!("Unknown standard {}", nft_canister.standard)),
+        };
+
+        TokenRequirement {
+            canister: nft_canister.principal,
+            standard: nft_canister.standard.clone(),
+            required_amount: nft_canister.amount,
+            balance: balance.clone().unwrap_or_default(),
+            is_met: balance
+                .as_ref()
+                .is_ok_and(|_balance| *_balance >= nft_canister.amount as u128),
+            error: balance.err(),
         }
     }
 
@@ -960,6 +2624,17 @@ impl Store {
         governance_canister: Principal,
         rules: Vec<NeuronGatedRules>,
     ) -> bool {
+        Self::get_neuron_requirement(principal, governance_canister, rules)
+            .await
+            .is_met
+    }
+
+    // Method to get the neurons of the caller with the rules each neuron fails
+    async fn get_neuron_requirement(
+        principal: Principal,
+        governance_canister: Principal,
+        rules: Vec<NeuronGatedRules>,
+    ) -> NeuronRequirement {
         let list_neuron_arg = ListNeurons {
             of_principal: Some(principal),
             limit: 100,
@@ -970,329 +2645,743 @@ impl Store {
             call::call(governance_canister, "list_neurons", (list_neuron_arg,)).await;
 
         match call {
-            Ok((neurons,)) => {
-                let mut is_valid: HashMap<Vec<u8>, bool> = HashMap::new();
-                // iterate over the neurons and check if the neuron applies to all the set rules
-                for neuron in neurons.neurons {
-                    let neuron_id = neuron.id.unwrap().id;
-                    is_valid.insert(neuron_id.clone(), true);
-                    for rule in rules.clone() {
-                        match rule {
-                            NeuronGatedRules::IsDisolving(_) => {
-                                match &neuron.dissolve_state {
-                                    Some(_state) => {
-                                        use DissolveState::*;
-                                        match _state {
-                                            // neuron is not in a dissolving state
-                                            DissolveDelaySeconds(_time) => {
-                                                is_valid.insert(neuron_id, false);
-                                                break;
-                                            }
-                                            // means that the neuron is in a dissolving state
-                                            WhenDissolvedTimestampSeconds(_time) => {}
-                                        }
-                                    }
-                                    None => {
-                                        is_valid.insert(neuron_id, false);
-                                        break;
-                                    }
-                                }
-                            }
-                            NeuronGatedRules::MinAge(_min_age_in_seconds) => {
-                                if neuron.created_timestamp_seconds < _min_age_in_seconds {
-                                    is_valid.insert(neuron_id, false);
-                                    break;
+            Ok((response,)) => {
+                let neurons: Vec<NeuronEligibility> = response
+                    .neurons
+                    .iter()
+                    .map(|neuron| {
+                        let (dissolve_delay_seconds, when_dissolved_timestamp_seconds) =
+                            match &neuron.dissolve_state {
+                                Some(DissolveState::DissolveDelaySeconds(_seconds)) => {
+                                    (Some(*_seconds), None)
                                 }
-                            }
-                            NeuronGatedRules::MinStake(_min_stake) => {
-                                let neuron_stake =
-                                    neuron.cached_neuron_stake_e8s as f64 / 100_000_000.0;
-                                let min_stake = _min_stake as f64 / 100_000_000.0;
-
-                                if neuron_stake.ceil() < min_stake.ceil() {
-                                    is_valid.insert(neuron_id, false);
-                                    break;
+                                Some(DissolveState::WhenDissolvedTimestampSeconds(_timestamp)) => {
+                                    (None, Some(*_timestamp))
                                 }
+                                None => (None, None),
+                            };
+
+                        NeuronEligibility {
+                            neuron_id: neuron
+                                .id
+                                .as_ref()
+                                .map(|id| id.id.clone())
+                                .unwrap_or_default(),
+                            stake_e8s: neuron.cached_neuron_stake_e8s,
+                            created_timestamp_seconds: neuron.created_timestamp_seconds,
+                            dissolve_delay_seconds,
+                            when_dissolved_timestamp_seconds,
+                            failed_rules: Self::get_failed_neuron_rules(neuron, &rules),
+                        }
+                    })
+                    .collect();
+
+                NeuronRequirement {
+                    governance_canister,
+                    is_met: neurons.iter().any(|neuron| neuron.failed_rules.is_empty()),
+                    rules,
+                    neurons,
+                    error: None,
+                }
+            }
+            Err((_, err)) => NeuronRequirement {
+                governance_canister,
+                rules,
+                neurons: vec![],
+                is_met: false,
+                error: Some(err),
+            },
+        }
+    }
+
+    // Method to get the rules a neuron doesnt apply to
+    fn get_failed_neuron_rules(
+        neuron: &Neuron,
+        rules: &[NeuronGatedRules],
+    ) -> Vec<NeuronGatedRules> {
+        rules
+            .iter()
+            .filter(|rule| match rule {
+                // the neuron needs to be in a dissolving state
+                NeuronGatedRules::IsDisolving(_) => !matches!(
+                    neuron.dissolve_state,
+                    Some(DissolveState::WhenDissolvedTimestampSeconds(_))
+                ),
+                NeuronGatedRules::MinAge(_min_age_in_seconds) => {
+                    neuron.created_timestamp_seconds < *_min_age_in_seconds
+                }
+                NeuronGatedRules::MinStake(_min_stake) => {
+                    let neuron_stake = neuron.cached_neuron_stake_e8s as f64 / 100_000_000.0;
+                    let min_stake = *_min_stake as f64 / 100_000_000.0;
+                    neuron_stake.ceil() < min_stake.ceil()
+                }
+                // the neuron cant be dissolving, the locking period needs to be at least the min dissolve delay
+                NeuronGatedRules::MinDissolveDelay(_min_dissolve_delay_in_seconds) => {
+                    match &neuron.dissolve_state {
+                        Some(DissolveState::DissolveDelaySeconds(_dissolve_delay_in_seconds)) => {
+                            _min_dissolve_delay_in_seconds > _dissolve_delay_in_seconds
+                        }
+                        _ => true,
+                    }
+                }
+            })
+            .cloned()
+            .collect()
+    }
+
+    // Method to transfer the ownership of a group to an existing member of the group
+    pub async fn transfer_ownership(
+        caller: Principal,
+        group_identifier: Principal,
+        new_owner_member_identifier: Principal,
+    ) -> Result<GroupResponse, ApiError> {
+        let inputs = Some(vec![
+            format!("caller - {:?}", &caller.to_string()),
+            format!("group_identifier - {:?}", &group_identifier),
+            format!(
+                "new_owner_member_identifier - {:?}",
+                &new_owner_member_identifier
+            ),
+        ]);
+
+        let _group_data =
+            Self::get_owned_group(caller, group_identifier, "transfer_ownership", &inputs)?;
+        let new_owner = Self::get_new_owner(
+            caller,
+            group_identifier,
+            new_owner_member_identifier,
+            "transfer_ownership",
+            &inputs,
+        )
+        .await?;
+
+        Self::set_owner(
+            group_identifier,
+            caller,
+            new_owner,
+            new_owner_member_identifier,
+            false,
+            "transfer_ownership",
+            inputs,
+        )
+    }
+
+    // Method to propose a new owner for a group, the proposed member needs to accept the ownership before it expires
+    pub async fn propose_ownership_transfer(
+        caller: Principal,
+        group_identifier: Principal,
+        new_owner_member_identifier: Principal,
+        expires_in_seconds: Option<u64>,
+    ) -> Result<GroupResponse, ApiError> {
+        let inputs = Some(vec![
+            format!("caller - {:?}", &caller.to_string()),
+            format!("group_identifier - {:?}", &group_identifier),
+            format!(
+                "new_owner_member_identifier - {:?}",
+                &new_owner_member_identifier
+            ),
+            format!("expires_in_seconds - {:?}", &expires_in_seconds),
+        ]);
+
+        let expires_in_seconds =
+            expires_in_seconds.unwrap_or(OWNERSHIP_TRANSFER_DEFAULT_EXPIRY_SECONDS);
+        if expires_in_seconds == 0 || expires_in_seconds > OWNERSHIP_TRANSFER_MAX_EXPIRY_SECONDS {
+            return Err(api_error(
+                ApiErrorType::BadRequest,
+                "INVALID_EXPIRY",
+                "The expiry of an ownership transfer must be between 1 second and 30 days",
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                "propose_ownership_transfer",
+                inputs,
+            ));
+        }
+
+        Self::get_owned_group(
+            caller,
+            group_identifier,
+            "propose_ownership_transfer",
+            &inputs,
+        )?;
+        let new_owner = Self::get_new_owner(
+            caller,
+            group_identifier,
+            new_owner_member_identifier,
+            "propose_ownership_transfer",
+            &inputs,
+        )
+        .await?;
+
+        // Get the latest group data, the group could have changed during the inter-canister call
+        let mut _group_data = Self::get_owned_group(
+            caller,
+            group_identifier,
+            "propose_ownership_transfer",
+            &inputs,
+        )?;
+
+        let previous_group_data = _group_data.clone();
+
+        // A new proposal replaces the existing pending ownership transfer
+        _group_data.pending_ownership_transfer = Some(OwnershipTransferOffer {
+            new_owner,
+            new_owner_member_identifier,
+            offered_by: caller,
+            expires_on: time() + expires_in_seconds * 1_000_000_000,
+            created_on: time(),
+        });
+        _group_data.updated_on = time();
+
+        let update_group_result = STABLE_DATA.with(|data| {
+            Self::write_group(
+                data,
+                caller,
+                "propose_ownership_transfer",
+                group_identifier,
+                &previous_group_data,
+                _group_data,
+                None,
+            )
+        });
+
+        match update_group_result {
+            Err(err) => Err(err),
+            Ok((_identifier, _group_data)) => Ok(Self::map_group_to_group_response(
+                _identifier.to_string(),
+                _group_data,
+            )),
+        }
+    }
+
+    // Method for the proposed owner to accept a pending ownership transfer
+    pub async fn accept_ownership(
+        caller: Principal,
+        group_identifier: Principal,
+    ) -> Result<GroupResponse, ApiError> {
+        let inputs = Some(vec![
+            format!("caller - {:?}", &caller.to_string()),
+            format!("group_identifier - {:?}", &group_identifier),
+        ]);
+
+        let (_group_data, _offer) =
+            Self::get_ownership_offer(caller, group_identifier, "accept_ownership", &inputs)?;
+
+        // the proposed owner could have left the group after the proposal
+        let member = Self::get_new_owner(
+            _group_data.owner,
+            group_identifier,
+            _offer.new_owner_member_identifier,
+            "accept_ownership",
+            &inputs,
+        )
+        .await?;
+
+        if member != caller {
+            return Err(api_error(
+                ApiErrorType::Unauthorized,
+                "NOT_PROPOSED_OWNER",
+                "The proposed member doesnt belong to you",
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                "accept_ownership",
+                inputs,
+            ));
+        }
+
+        Self::set_owner(
+            group_identifier,
+            _group_data.owner,
+            caller,
+            _offer.new_owner_member_identifier,
+            true,
+            "accept_ownership",
+            inputs,
+        )
+    }
+
+    // Method for the proposed owner to decline a pending ownership transfer
+    pub fn decline_ownership(
+        caller: Principal,
+        group_identifier: Principal,
+    ) -> Result<(), ApiError> {
+        let inputs = Some(vec![
+            format!("caller - {:?}", &caller.to_string()),
+            format!("group_identifier - {:?}", &group_identifier),
+        ]);
+
+        let (mut _group_data, _) =
+            Self::get_ownership_offer(caller, group_identifier, "decline_ownership", &inputs)?;
+
+        let previous_group_data = _group_data.clone();
+        _group_data.pending_ownership_transfer = None;
+        _group_data.updated_on = time();
+
+        let update_group_result = STABLE_DATA.with(|data| {
+            Self::write_group(
+                data,
+                caller,
+                "decline_ownership",
+                group_identifier,
+                &previous_group_data,
+                _group_data,
+                None,
+            )
+        });
+
+        match update_group_result {
+            Err(err) => Err(err),
+            Ok(_) => Ok(()),
+        }
+    }
+
+    // Method to get a group that is not deleted and owned by the caller
+    fn get_owned_group(
+        caller: Principal,
+        group_identifier: Principal,
+        method_name: &str,
+        inputs: &Option<Vec<String>>,
+    ) -> Result<Group, ApiError> {
+        STABLE_DATA.with(|data| {
+            match ENTRIES.with(|entries| Data::get_entry(data, entries, group_identifier)) {
+                Err(err) => Err(err),
+                Ok((_, _group_data)) => {
+                    if _group_data.is_deleted {
+                        return Err(api_error(
+                            ApiErrorType::BadRequest,
+                            "DELETED_GROUP",
+                            "You cant transfer the ownership of a deleted group",
+                            Data::get_name(data.borrow().get()).as_str(),
+                            method_name,
+                            inputs.clone(),
+                        ));
+                    }
+
+                    if _group_data.owner != caller {
+                        return Err(api_error(
+                            ApiErrorType::Unauthorized,
+                            "CANT_TRANSFER_OWNERSHIP",
+                            "Only the owner of the group can transfer ownership",
+                            Data::get_name(data.borrow().get()).as_str(),
+                            method_name,
+                            inputs.clone(),
+                        ));
+                    }
+
+                    Ok(_group_data)
+                }
+            }
+        })
+    }
+
+    // Method to get a group with a pending ownership transfer to the caller
+    fn get_ownership_offer(
+        caller: Principal,
+        group_identifier: Principal,
+        method_name: &str,
+        inputs: &Option<Vec<String>>,
+    ) -> Result<(Group, OwnershipTransferOffer), ApiError> {
+        STABLE_DATA.with(|data| {
+            match ENTRIES.with(|entries| Data::get_entry(data, entries, group_identifier)) {
+                Err(err) => Err(err),
+                Ok((_, _group_data)) => {
+                    if _group_data.is_deleted {
+                        return Err(api_error(
+                            ApiErrorType::BadRequest,
+                            "DELETED_GROUP",
+                            "You cant transfer the ownership of a deleted group",
+                            Data::get_name(data.borrow().get()).as_str(),
+                            method_name,
+                            inputs.clone(),
+                        ));
+                    }
+
+                    // Expired offers are treated as if there is no pending ownership transfer
+                    let offer = _group_data
+                        .pending_ownership_transfer
+                        .clone()
+                        .filter(|offer| offer.expires_on > time());
+
+                    match offer {
+                        None => Err(api_error(
+                            ApiErrorType::NotFound,
+                            "NO_PENDING_OWNERSHIP_TRANSFER",
+                            "There is no pending ownership transfer for this group",
+                            Data::get_name(data.borrow().get()).as_str(),
+                            method_name,
+                            inputs.clone(),
+                        )),
+                        Some(_offer) => {
+                            if _offer.new_owner != caller {
+                                return Err(api_error(
+                                    ApiErrorType::Unauthorized,
+                                    "NOT_PROPOSED_OWNER",
+                                    "The ownership of this group is not offered to you",
+                                    Data::get_name(data.borrow().get()).as_str(),
+                                    method_name,
+                                    inputs.clone(),
+                                ));
                             }
-                            NeuronGatedRules::MinDissolveDelay(_min_dissolve_delay_in_seconds) => {
-                                match &neuron.dissolve_state {
-                                    Some(_state) => {
-                                        use DissolveState::*;
-                                        match _state {
-                                            // neuron is not in a dissolving state, time is locking period in seconds
-                                            DissolveDelaySeconds(_dissolve_delay_in_seconds) => {
-                                                if &_min_dissolve_delay_in_seconds
-                                                    > _dissolve_delay_in_seconds
-                                                {
-                                                    is_valid.insert(neuron_id, false);
-                                                    break;
-                                                }
-                                            }
-                                            // if the neuron is dissolving, make invalid
-                                            // means that the neuron is in a dissolving state, timestamp when neuron is done dissolving in seconds
-                                            WhenDissolvedTimestampSeconds(_) => {
-                                                is_valid.insert(neuron_id, false);
-                                                break;
-                                            }
-                                        }
-                                    }
-                                    None => {
-                                        is_valid.insert(neuron_id, false);
-                                        break;
-                                    }
-                                }
+                            Ok((_group_data, _offer))
+                        }
+                    }
+                }
+            }
+        })
+    }
+
+    // Method to check with the member canister if the new owner is an existing member of the group
+    async fn get_new_owner(
+        caller: Principal,
+        group_identifier: Principal,
+        new_owner_member_identifier: Principal,
+        method_name: &str,
+        inputs: &Option<Vec<String>>,
+    ) -> Result<Principal, ApiError> {
+        let new_owner = match get_member_roles(new_owner_member_identifier, group_identifier).await
+        {
+            Err(err) => {
+                return Err(api_error(
+                    ApiErrorType::NotFound,
+                    "MEMBER_NOT_FOUND",
+                    err.as_str(),
+                    STABLE_DATA
+                        .with(|data| Data::get_name(data.borrow().get()))
+                        .as_str(),
+                    method_name,
+                    inputs.clone(),
+                ))
+            }
+            Ok((_principal, _)) => _principal,
+        };
+
+        if new_owner == caller {
+            return Err(api_error(
+                ApiErrorType::BadRequest,
+                "ALREADY_OWNER",
+                "The member is already the owner of the group",
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                method_name,
+                inputs.clone(),
+            ));
+        }
+
+        Ok(new_owner)
+    }
+
+    // Method to store the new owner on the group, the owner role is swapped on the member canisters afterwards through jobs
+    // the member canisters that hold members of the group and the member canister of the new owner are called until they succeed
+    // the group data is fetched again because it could have changed during the inter-canister calls
+    fn set_owner(
+        group_identifier: Principal,
+        previous_owner: Principal,
+        new_owner: Principal,
+        new_owner_member_identifier: Principal,
+        requires_offer: bool,
+        method_name: &str,
+        inputs: Option<Vec<String>>,
+    ) -> Result<GroupResponse, ApiError> {
+        // an offer is accepted by the new owner, a direct transfer is done by the previous owner
+        let caller = match requires_offer {
+            true => new_owner,
+            false => previous_owner,
+        };
+
+        STABLE_DATA.with(|data| {
+            match ENTRIES.with(|entries| Data::get_entry(data, entries, group_identifier)) {
+                Err(err) => Err(err),
+                Ok((_identifier, mut _group_data)) => {
+                    let has_offer = _group_data
+                        .pending_ownership_transfer
+                        .as_ref()
+                        .is_some_and(|offer| offer.new_owner == new_owner);
+
+                    if _group_data.owner != previous_owner || (requires_offer && !has_offer) {
+                        return Err(api_error(
+                            ApiErrorType::BadRequest,
+                            "OWNERSHIP_CHANGED",
+                            "The ownership of the group changed during the transfer",
+                            Data::get_name(data.borrow().get()).as_str(),
+                            method_name,
+                            inputs,
+                        ));
+                    }
+
+                    let previous_group_data = _group_data.clone();
+
+                    _group_data.owner = new_owner;
+                    _group_data.pending_ownership_transfer = None;
+                    _group_data.updated_on = time();
+
+                    match Self::write_group(
+                        data,
+                        caller,
+                        method_name,
+                        _identifier,
+                        &previous_group_data,
+                        _group_data,
+                        None,
+                    ) {
+                        Err(err) => Err(err),
+                        Ok((_identifier, _group_data)) => {
+                            let new_owner_member_canister =
+                                RoleGrants::get_member_canister(new_owner_member_identifier);
+                            let mut member_canisters: Vec<Principal> =
+                                _group_data.member_count.keys().copied().collect();
+                            if !member_canisters.contains(&new_owner_member_canister) {
+                                member_canisters.push(new_owner_member_canister);
                             }
+
+                            Jobs::add_jobs(
+                                _identifier,
+                                GroupJobKind::OwnershipTransferred {
+                                    previous_owner,
+                                    new_owner,
+                                },
+                                member_canisters,
+                            );
+                            Ok(Self::map_group_to_group_response(
+                                _identifier.to_string(),
+                                _group_data,
+                            ))
                         }
                     }
                 }
-                return is_valid.iter().any(|v| v.1 == &true);
             }
-            Err(_) => false,
-        }
+        })
     }
 
-    // need to call the member canister to transfer the ownership to a new member
-    // pub fn transfer_ownership(
-    //     caller: Principal,
-    //     group_id: u64,
-    //     principal: Principal,
-    // ) -> Result<bool, ApiError> {
-    //     let inputs = Some(vec![
-    //         format!("caller - {:?}", &caller.to_string()),
-    //         format!("group_id - {:?}", &group_id),
-    //         format!("principal - {:?}", &principal.to_string()),
-    //     ]);
-    //     let _group = Data::get_entry::<Group>(group_id);
-    //     match _group {
-    //         Err(err) => Err(err),
-    //         Ok(_group_data) => {
-    //             if _group_data.data.owner.principal != caller {
-    //                 return Err(api_error(
-    //                     ApiErrorType::Unauthorized,
-    //                     "CANT_TRANSFER_OWNERSHIP",
-    //                     "Only the owner of the group can transfer ownership",
-    //                     Data::get_name().as_str(),
-    //                     "delete_group",
-    //                     inputs,
-    //                 ));
-    //             }
-
-    //             let updated_group = Group {
-    //                 name: _group_data.data.name,
-    //                 description: _group_data.data.description,
-    //                 website: _group_data.data.website,
-    //                 location: _group_data.data.location,
-    //                 privacy: _group_data.data.privacy,
-    //                 owner: principal,
-    //                 matrix_space_id: _group_data.data.matrix_space_id,
-    //                 image: _group_data.data.image,
-    //                 banner_image: _group_data.data.banner_image,
-    //                 tags: _group_data.data.tags,
-    //                 roles: _group_data.data.roles,
-    //                 is_deleted: _group_data.data.is_deleted,
-    //                 updated_on: time(),
-    //                 created_on: _group_data.data.created_on,
-    //             };
-    //             let update_group_result =
-    //                 Data::update_entry::<Group>(_group_data.identifier.id, updated_group.clone());
-
-    //             match update_group_result {
-    //                 Err(err) => Err(err),
-    //                 Ok(_) => Ok(true),
-    //             }
-    //         }
-    //     }
-    // }
-
-    // Method to get filtered groups
+    // Method to get the groups that match the filter expression
     fn get_filtered_groups(
         mut groups: Vec<GroupResponse>,
-        filters: Vec<GroupFilter>,
-        filter_type: FilterType,
+        expression: &GroupFilterExpression,
+        searches: &GroupSearches,
     ) -> Vec<GroupResponse> {
-        if let FilterType::Or = filter_type {
-            if filters.len() == 0 {
-                return groups;
+        groups.retain(|group| Self::matches_expression(group, expression, searches));
+        groups
+    }
+
+    // Method to check if a group matches a (nested) filter expression
+    fn matches_expression(
+        group: &GroupResponse,
+        expression: &GroupFilterExpression,
+        searches: &GroupSearches,
+    ) -> bool {
+        match expression {
+            GroupFilterExpression::Filter(filter) => Self::matches_filter(group, filter, searches),
+            GroupFilterExpression::And(expressions) => expressions
+                .iter()
+                .all(|expression| Self::matches_expression(group, expression, searches)),
+            GroupFilterExpression::Or(expressions) => expressions
+                .iter()
+                .any(|expression| Self::matches_expression(group, expression, searches)),
+            GroupFilterExpression::Not(expression) => {
+                !Self::matches_expression(group, expression, searches)
             }
         }
+    }
 
-        match filter_type {
-            // this filter type will return groups that match all the filters
-            FilterType::And => {
-                for filter in filters {
-                    match filter {
-                        GroupFilter::Name(value) => {
-                            groups = groups
-                                .into_iter()
-                                .filter(|group| {
-                                    group.name.to_lowercase().contains(&value.to_lowercase())
-                                })
-                                .collect();
-                        }
-                        GroupFilter::Tag(value) => {
-                            groups = groups
-                                .into_iter()
-                                .filter(|group| group.tags.contains(&value))
-                                .collect();
-                        }
-                        GroupFilter::UpdatedOn(value) => {
-                            groups = groups
-                                .into_iter()
-                                .filter(|group| {
-                                    if value.end_date > 0 {
-                                        return group.updated_on >= value.start_date
-                                            && group.updated_on <= value.end_date;
-                                    } else {
-                                        return group.updated_on >= value.start_date;
-                                    }
-                                })
-                                .collect();
-                        }
-                        GroupFilter::CreatedOn(value) => {
-                            groups = groups
-                                .into_iter()
-                                .filter(|group| {
-                                    if value.end_date > 0 {
-                                        return group.created_on >= value.start_date
-                                            && group.created_on <= value.end_date;
-                                    } else {
-                                        return group.created_on >= value.start_date;
-                                    }
-                                })
-                                .collect();
-                        }
-                        GroupFilter::Identifiers(value) => {
-                            groups = groups
-                                .into_iter()
-                                .filter(|group| value.contains(&group.identifier))
-                                .collect();
-                        }
-                        GroupFilter::Owner(value) => {
-                            groups = groups
-                                .into_iter()
-                                .filter(|group| group.owner == value)
-                                .collect();
-                        }
-                        GroupFilter::MemberCount(value) => {
-                            groups = groups
-                                .into_iter()
-                                .filter(|group| {
-                                    group.member_count >= value.0 && group.member_count <= value.1
-                                })
-                                .collect();
-                        }
-                    }
+    // Method to check if a group matches a single filter
+    fn matches_filter(
+        group: &GroupResponse,
+        filter: &GroupFilter,
+        searches: &GroupSearches,
+    ) -> bool {
+        match filter {
+            GroupFilter::Name(value) => group.name.to_lowercase().contains(&value.to_lowercase()),
+            GroupFilter::Tag(value) => group.tags.contains(value),
+            GroupFilter::UpdatedOn(value) => {
+                if value.end_date > 0 {
+                    group.updated_on >= value.start_date && group.updated_on <= value.end_date
+                } else {
+                    group.updated_on >= value.start_date
                 }
-                groups
             }
-            // This filter type will return groups that match any of the filters
-            FilterType::Or => {
-                let mut hashmap_groups: HashMap<Principal, GroupResponse> = HashMap::new();
-                for filter in filters {
-                    match filter {
-                        GroupFilter::Name(value) => {
-                            groups
-                                .iter()
-                                .filter(|group| {
-                                    group.name.to_lowercase().contains(&value.to_lowercase())
-                                })
-                                .for_each(|v| {
-                                    hashmap_groups.insert(v.identifier.clone(), v.clone());
-                                });
-                        }
-                        GroupFilter::Tag(value) => {
-                            groups
-                                .iter()
-                                .filter(|group| group.tags.contains(&value))
-                                .for_each(|v| {
-                                    hashmap_groups.insert(v.identifier.clone(), v.clone());
-                                });
-                        }
-                        GroupFilter::UpdatedOn(value) => {
-                            groups
-                                .iter()
-                                .filter(|group| {
-                                    if value.end_date > 0 {
-                                        return group.updated_on >= value.start_date
-                                            && group.updated_on <= value.end_date;
-                                    } else {
-                                        return group.updated_on >= value.start_date;
-                                    }
-                                })
-                                .for_each(|v| {
-                                    hashmap_groups.insert(v.identifier.clone(), v.clone());
-                                });
-                        }
-                        GroupFilter::CreatedOn(value) => {
-                            groups
-                                .iter()
-                                .filter(|group| {
-                                    if value.end_date > 0 {
-                                        return group.created_on >= value.start_date
-                                            && group.created_on <= value.end_date;
-                                    } else {
-                                        return group.created_on >= value.start_date;
-                                    }
-                                })
-                                .for_each(|v| {
-                                    hashmap_groups.insert(v.identifier.clone(), v.clone());
-                                });
-                        }
-                        GroupFilter::Identifiers(value) => {
-                            groups
-                                .iter()
-                                .filter(|group| value.contains(&group.identifier))
-                                .for_each(|v| {
-                                    hashmap_groups.insert(v.identifier.clone(), v.clone());
-                                });
-                        }
-                        GroupFilter::Owner(value) => {
-                            groups
-                                .iter()
-                                .filter(|group| group.owner == value)
-                                .for_each(|v| {
-                                    hashmap_groups.insert(v.identifier.clone(), v.clone());
-                                });
-                        }
-                        GroupFilter::MemberCount(value) => {
-                            groups
-                                .iter()
-                                .filter(|group| {
-                                    group.member_count >= value.0 && group.member_count <= value.1
-                                })
-                                .for_each(|v| {
-                                    hashmap_groups.insert(v.identifier.clone(), v.clone());
-                                });
-                        }
-                    }
+            GroupFilter::CreatedOn(value) => {
+                if value.end_date > 0 {
+                    group.created_on >= value.start_date && group.created_on <= value.end_date
+                } else {
+                    group.created_on >= value.start_date
                 }
+            }
+            GroupFilter::Identifiers(value) => value.contains(&group.identifier),
+            GroupFilter::Owner(value) => &group.owner == value,
+            GroupFilter::MemberCount(value) => {
+                group.member_count >= value.0 && group.member_count <= value.1
+            }
+            // A search without searchable words matches all groups
+            GroupFilter::Search(value) => match searches.get(value) {
+                Some(Some(matches)) => matches.contains_key(&group.identifier),
+                _ => true,
+            },
+            // Groups without a physical location never match a location filter
+            GroupFilter::NearLocation {
+                lat,
+                lng,
+                radius_km,
+            } => Self::get_coordinates(&group.location).is_some_and(|coordinates| {
+                Self::get_distance_km(coordinates, (*lat, *lng)) <= *radius_km
+            }),
+            GroupFilter::BoundingBox {
+                min_lat,
+                min_lng,
+                max_lat,
+                max_lng,
+            } => Self::get_coordinates(&group.location).is_some_and(|(lat, lng)| {
+                let in_lng = if min_lng <= max_lng {
+                    lng >= *min_lng && lng <= *max_lng
+                } else {
+                    lng >= *min_lng || lng <= *max_lng
+                };
+                lat >= *min_lat && lat <= *max_lat && in_lng
+            }),
+            GroupFilter::Privacy(value) => value.matches(&group.privacy),
+        }
+    }
 
-                hashmap_groups.into_iter().map(|v| v.1).collect()
+    // Method to get the (lat, lng) coordinates of a group, `None` if the group has no physical location
+    fn get_coordinates(location: &Location) -> Option<(f64, f64)> {
+        match location {
+            Location::Physical(physical) => {
+                Some((physical.lattitude as f64, physical.longtitude as f64))
             }
+            Location::MultiLocation(multi_location) => Some((
+                multi_location.physical.lattitude as f64,
+                multi_location.physical.longtitude as f64,
+            )),
+            Location::None | Location::Digital(_) => None,
         }
     }
 
-    // Method to get sorted groups
-    fn get_ordered_groups(mut groups: Vec<GroupResponse>, sort: GroupSort) -> Vec<GroupResponse> {
-        match sort {
-            GroupSort::CreatedOn(direction) => match direction {
-                SortDirection::Asc => groups.sort_by(|a, b| a.created_on.cmp(&b.created_on)),
-                SortDirection::Desc => groups.sort_by(|a, b| b.created_on.cmp(&a.created_on)),
-            },
-            GroupSort::UpdatedOn(direction) => match direction {
-                SortDirection::Asc => groups.sort_by(|a, b| a.updated_on.cmp(&b.updated_on)),
-                SortDirection::Desc => groups.sort_by(|a, b| b.updated_on.cmp(&a.updated_on)),
-            },
-            GroupSort::Name(direction) => match direction {
-                SortDirection::Asc => groups.sort_by(|a, b| a.name.cmp(&b.name)),
-                SortDirection::Desc => groups.sort_by(|a, b| b.name.cmp(&a.name)),
-            },
-            GroupSort::MemberCount(direction) => match direction {
-                SortDirection::Asc => groups.sort_by(|a, b| a.member_count.cmp(&b.member_count)),
-                SortDirection::Desc => groups.sort_by(|a, b| b.member_count.cmp(&a.member_count)),
-            },
+    // Method to get the great-circle distance in km between two (lat, lng) coordinates (haversine)
+    fn get_distance_km((a_lat, a_lng): (f64, f64), (b_lat, b_lng): (f64, f64)) -> f64 {
+        let delta_lat = (b_lat - a_lat).to_radians();
+        let delta_lng = (b_lng - a_lng).to_radians();
+
+        let a = (delta_lat / 2.0).sin().powi(2)
+            + a_lat.to_radians().cos() * b_lat.to_radians().cos() * (delta_lng / 2.0).sin().powi(2);
+
+        EARTH_RADIUS_KM * 2.0 * a.sqrt().asin()
+    }
+
+    // Method to run the `Search` filters of the expression once, so they can be checked per group
+    fn get_searches(expression: &GroupFilterExpression) -> GroupSearches {
+        expression
+            .filters()
+            .into_iter()
+            .filter_map(|filter| match filter {
+                GroupFilter::Search(value) => Some(value),
+                _ => None,
+            })
+            .map(|value| (value.clone(), SearchIndex::search(value)))
+            .collect()
+    }
+
+    // Method to get the next `limit` groups after the cursor, ordered by their sort key
+    fn get_sorted_groups(
+        groups: Vec<GroupResponse>,
+        query: GroupChunkQuery,
+        relevance: &HashMap<Principal, u32>,
+    ) -> SortedGroups {
+        let total = groups.len();
+
+        let mut sorted_groups: Vec<(GroupSortKey, GroupResponse)> = groups
+            .into_iter()
+            .map(|group| (Self::get_sort_key(&query.sort, &group, relevance), group))
+            // Skip the groups up to and including the cursor
+            .filter(|(_sort_key, _group)| match &query.cursor {
+                None => true,
+                Some(_cursor) => {
+                    query.sort.compare(
+                        (_sort_key, &_group.identifier),
+                        (&_cursor.sort_key, &_cursor.identifier),
+                    ) == Ordering::Greater
+                }
+            })
+            .collect();
+
+        let compare = |(a_key, a): &(GroupSortKey, GroupResponse),
+                       (b_key, b): &(GroupSortKey, GroupResponse)| {
+            query
+                .sort
+                .compare((a_key, &a.identifier), (b_key, &b.identifier))
         };
-        groups
+
+        // Only the top `limit` groups are returned, select them first so only those need to be sorted
+        let has_more = sorted_groups.len() > query.limit;
+        if has_more {
+            sorted_groups.select_nth_unstable_by(query.limit, compare);
+            sorted_groups.truncate(query.limit);
+        }
+        sorted_groups.sort_by(compare);
+
+        SortedGroups {
+            total,
+            has_more,
+            groups: sorted_groups,
+        }
+    }
+
+    // Method to get the value a group is sorted on
+    fn get_sort_key(
+        sort: &GroupSort,
+        group: &GroupResponse,
+        relevance: &HashMap<Principal, u32>,
+    ) -> GroupSortKey {
+        match sort {
+            GroupSort::Name(_) => GroupSortKey::Name(group.name.clone()),
+            GroupSort::MemberCount(_) => GroupSortKey::MemberCount(group.member_count),
+            GroupSort::CreatedOn(_) => GroupSortKey::CreatedOn(group.created_on),
+            GroupSort::UpdatedOn(_) => GroupSortKey::UpdatedOn(group.updated_on),
+            GroupSort::Relevance => {
+                GroupSortKey::Relevance(relevance.get(&group.identifier).copied().unwrap_or(0))
+            }
+            GroupSort::Distance(lat, lng) => GroupSortKey::Distance(
+                Self::get_coordinates(&group.location)
+                    .map(|coordinates| Self::get_distance_km(coordinates, (*lat, *lng)))
+                    .filter(|distance| distance.is_finite())
+                    .unwrap_or(f64::MAX),
+            ),
+            GroupSort::Privacy(_) => GroupSortKey::Privacy(match &group.privacy {
+                Privacy::Public => 0,
+                Privacy::Private => 1,
+                Privacy::InviteOnly => 2,
+                Privacy::Gated(GatedType::Token(_)) => 3,
+                Privacy::Gated(GatedType::Neuron(_)) => 4,
+            }),
+        }
+    }
+
+    // Method to get the combined search score per group of all the `Search` filters
+    fn get_search_relevance(searches: &GroupSearches) -> HashMap<Principal, u32> {
+        let mut relevance: HashMap<Principal, u32> = HashMap::new();
+        for matches in searches.values().flatten() {
+            for (identifier, score) in matches {
+                *relevance.entry(*identifier).or_default() += score;
+            }
+        }
+        relevance
+    }
+
+    // Method to get sorted groups
+    fn get_ordered_groups(
+        groups: Vec<GroupResponse>,
+        sort: GroupSort,
+        relevance: &HashMap<Principal, u32>,
+    ) -> Vec<GroupResponse> {
+        let mut sorted_groups: Vec<(GroupSortKey, GroupResponse)> = groups
+            .into_iter()
+            .map(|group| (Self::get_sort_key(&sort, &group, relevance), group))
+            .collect();
+
+        sorted_groups.sort_by(|(a_key, a), (b_key, b)| {
+            sort.compare((a_key, &a.identifier), (b_key, &b.identifier))
+        });
+
+        sorted_groups.into_iter().map(|(_, group)| group).collect()
     }
 
     // Method to map groups to a default response that can be used on the frontend
@@ -1321,6 +3410,12 @@ impl Store {
             member_count: group.member_count.into_iter().map(|(_, value)| value).sum(),
             is_deleted: group.is_deleted,
             privacy_gated_type_amount: group.privacy_gated_type_amount,
+            // Expired ownership transfers are no longer pending
+            pending_ownership_transfer: group
+                .pending_ownership_transfer
+                .filter(|offer| offer.expires_on > time()),
+            deleted_on: group.deleted_on,
+            revision: group.revision.unwrap_or(0),
             updated_on: group.updated_on,
             created_on: group.created_on,
         }
@@ -1344,10 +3439,26 @@ impl Store {
             let existing = ENTRIES.with(|entries| Data::get_entry(data, entries, group_identifier));
             match existing {
                 Ok((_, mut _group)) => {
+                    let previous_group = _group.clone();
                     _group.member_count.insert(member_canister, member_count);
-                    let _ = ENTRIES.with(|entries| {
+                    // Member counts are not an edit of the group, so the revision is not bumped
+                    // otherwise every join or leave would reject pending edits that pass an `expected_revision`
+                    // the change is still added to the audit log, the member canister is the caller of this method
+                    let updated = ENTRIES.with(|entries| {
                         Data::update_entry(data, entries, group_identifier, _group)
                     });
+                    if let Ok((_identifier, _group_data)) = updated {
+                        let identifier = _identifier.to_string();
+                        Self::remove_from_indexes(&identifier, &previous_group);
+                        Self::add_to_indexes(&identifier, &_group_data);
+                        AuditLog::add_record(
+                            member_canister,
+                            "update_member_count",
+                            _identifier,
+                            Some(&previous_group),
+                            Some(&_group_data),
+                        );
+                    }
                     Ok(())
                 }
                 Err(_) => Err(false),
@@ -1408,19 +3519,209 @@ impl Store {
         .await
     }
 
+    // This method is used for role hierarchy based access control
+    // the caller needs the edit permission for roles, only roles ranked below the highest role of the caller can be managed
+    pub async fn can_manage_roles(
+        caller: Principal,
+        group_identifier: Principal,
+        member_identifier: Principal,
+    ) -> Result<RoleManager, ApiError> {
+        if let Ok((_, _group)) = STABLE_DATA
+            .with(|data| ENTRIES.with(|entries| Data::get_entry(data, entries, group_identifier)))
+        {
+            if _group.owner == caller {
+                return Ok(RoleManager::owner(caller));
+            }
+        }
+
+        let member_roles = Self::get_permitted_member_roles(
+            caller,
+            group_identifier,
+            member_identifier,
+            vec![GroupPermission::Role],
+            PermissionActionType::Edit,
+        )
+        .await?;
+
+        let group_roles = Self::get_group_roles(group_identifier);
+        Ok(RoleManager {
+            principal: caller,
+            rank: RoleHierarchy::get_member_rank(&group_roles, &member_roles),
+            roles: Some(member_roles),
+        })
+    }
+
+    // Method to check if the caller can manage the invites of a group, returns the rank of the highest role of the caller
+    pub async fn can_manage_invites(
+        caller: Principal,
+        group_identifier: Principal,
+        member_identifier: Principal,
+    ) -> Result<RoleManager, ApiError> {
+        if let Ok((_, _group)) = STABLE_DATA
+            .with(|data| ENTRIES.with(|entries| Data::get_entry(data, entries, group_identifier)))
+        {
+            if _group.owner == caller {
+                return Ok(RoleManager::owner(caller));
+            }
+        }
+
+        let member_roles = Self::get_permitted_member_roles(
+            caller,
+            group_identifier,
+            member_identifier,
+            vec![GroupPermission::Invite],
+            PermissionActionType::Write,
+        )
+        .await?;
+
+        let group_roles = Self::get_group_roles(group_identifier);
+        Ok(RoleManager {
+            principal: caller,
+            rank: RoleHierarchy::get_member_rank(&group_roles, &member_roles),
+            roles: Some(member_roles),
+        })
+    }
+
+    // This method is used for role / permission based access control of the wallets of a group
+    // the owner can always manage the wallets, other members need the edit permission for wallets
+    pub async fn can_manage_wallets(
+        caller: Principal,
+        group_identifier: Principal,
+        member_identifier: Option<Principal>,
+    ) -> Result<Principal, ApiError> {
+        if let Ok((_, _group)) = STABLE_DATA
+            .with(|data| ENTRIES.with(|entries| Data::get_entry(data, entries, group_identifier)))
+        {
+            if _group.owner == caller {
+                return Ok(caller);
+            }
+        }
+
+        match member_identifier {
+            None => Err(api_error(
+                ApiErrorType::Unauthorized,
+                "NO_PERMISSION",
+                "Only the owner can manage the wallets without a member identifier",
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                "can_manage_wallets",
+                None,
+            )),
+            Some(_member_identifier) => Self::get_permitted_member_roles(
+                caller,
+                group_identifier,
+                _member_identifier,
+                vec![GroupPermission::Wallet],
+                PermissionActionType::Edit,
+            )
+            .await
+            .map(|_| caller),
+        }
+    }
+
     // This method is used for role / permission based access control
+    // the owner can always delete the group, other members need the delete permission for `group_delete`
     pub async fn can_delete(
         caller: Principal,
         group_identifier: Principal,
         member_identifier: Principal,
     ) -> Result<Principal, ApiError> {
-        Self::check_permission(
+        if let Ok((_, _group)) = STABLE_DATA
+            .with(|data| ENTRIES.with(|entries| Data::get_entry(data, entries, group_identifier)))
+        {
+            if _group.owner == caller {
+                return Ok(caller);
+            }
+        }
+
+        Self::get_permitted_member_roles(
             caller,
             group_identifier,
             member_identifier,
+            vec![GroupPermission::Delete],
             PermissionActionType::Delete,
         )
         .await
+        .map(|_| caller)
+    }
+
+    // This method is used for role / permission based access control of profile changes
+    // the owner can always edit the group, other members need the edit permission for every part of the profile that changes
+    // (`group_profile`, `group_image` and `group_privacy`)
+    pub async fn can_edit_profile(
+        caller: Principal,
+        group_identifier: Principal,
+        member_identifier: Principal,
+        update_group: &UpdateGroup,
+    ) -> Result<GroupEditor, ApiError> {
+        let group = STABLE_DATA
+            .with(|data| ENTRIES.with(|entries| Data::get_entry(data, entries, group_identifier)));
+
+        let permissions = match group {
+            Err(err) => return Err(err),
+            Ok((_, _group)) => {
+                if _group.owner == caller {
+                    return Ok(GroupEditor {
+                        principal: caller,
+                        roles: None,
+                    });
+                }
+                Self::get_profile_permissions(&_group, update_group)
+            }
+        };
+
+        let member_roles = Self::get_permitted_member_roles(
+            caller,
+            group_identifier,
+            member_identifier,
+            permissions,
+            PermissionActionType::Edit,
+        )
+        .await?;
+
+        Ok(GroupEditor {
+            principal: caller,
+            roles: Some(member_roles),
+        })
+    }
+
+    // Method to get the permissions needed to change the profile of a group to the passed profile
+    // the generic `group` permission is needed if nothing changes
+    fn get_profile_permissions(group: &Group, update_group: &UpdateGroup) -> Vec<GroupPermission> {
+        let mut permissions: Vec<GroupPermission> = vec![];
+
+        if group.name != update_group.name
+            || group.description != update_group.description
+            || group.website != update_group.website
+            || !Self::is_same_value(&group.location, &update_group.location)
+            || group.tags != update_group.tags
+        {
+            permissions.push(GroupPermission::Profile);
+        }
+
+        if !Self::is_same_value(&group.image, &update_group.image)
+            || !Self::is_same_value(&group.banner_image, &update_group.banner_image)
+        {
+            permissions.push(GroupPermission::Image);
+        }
+
+        if group.privacy != update_group.privacy
+            || group.privacy_gated_type_amount != update_group.privacy_gated_type_amount
+        {
+            permissions.push(GroupPermission::Privacy);
+        }
+
+        if permissions.is_empty() {
+            permissions.push(GroupPermission::Group);
+        }
+
+        permissions
+    }
+
+    // Location and assets have no `PartialEq`, they are compared on their serialized structure
+    fn is_same_value<T: Serialize>(a: &T, b: &T) -> bool {
+        serde_json::to_value(a).ok() == serde_json::to_value(b).ok()
     }
 
     // Global method to determine if a member has a specific permission
@@ -1430,9 +3731,29 @@ impl Store {
         member_identifier: Principal,
         permission: PermissionActionType,
     ) -> Result<Principal, ApiError> {
+        Self::get_permitted_member_roles(
+            caller,
+            group_identifier,
+            member_identifier,
+            vec![GroupPermission::Group],
+            permission,
+        )
+        .await
+        .map(|_| caller)
+    }
+
+    // Method to get the roles of a member after checking the member belongs to the caller and has all the permissions
+    async fn get_permitted_member_roles(
+        caller: Principal,
+        group_identifier: Principal,
+        member_identifier: Principal,
+        permissions: Vec<GroupPermission>,
+        action: PermissionActionType,
+    ) -> Result<Vec<String>, ApiError> {
         let mut group_roles = Store::get_group_roles(group_identifier);
         group_roles.append(&mut default_roles());
-        let member_roles = get_member_roles(member_identifier, group_identifier).await;
+        let member_roles =
+            MemberRoleCache::get_member_roles(member_identifier, group_identifier).await;
 
         match member_roles {
             Ok((_principal, _roles)) => {
@@ -1449,14 +3770,14 @@ impl Store {
                     ));
                 }
 
-                let has_permission = has_permission(
-                    &_roles,
-                    &PermissionType::Group(None),
-                    &group_roles,
-                    &permission,
-                );
+                // Temporary roles that are expired dont count, even if they are not revoked yet
+                let _roles =
+                    RoleGrants::remove_expired_roles(group_identifier, member_identifier, _roles);
 
-                if !has_permission {
+                if !permissions
+                    .iter()
+                    .all(|permission| permission.is_granted(&_roles, &group_roles, &action))
+                {
                     return Err(api_error(
                         ApiErrorType::Unauthorized,
                         "NO_PERMISSION",
@@ -1472,7 +3793,7 @@ impl Store {
                     ));
                 }
 
-                Ok(caller)
+                Ok(_roles)
             }
             Err(err) => Err(api_error(
                 ApiErrorType::Unauthorized,
@@ -1487,3 +3808,118 @@ impl Store {
         }
     }
 }
+
+// Groups with only the fields that are used by the filter tests
+#[cfg(test)]
+fn test_groups(groups: Vec<(u8, &str, Vec<u32>, Privacy)>) -> Vec<GroupResponse> {
+    groups
+        .into_iter()
+        .map(|(id, name, tags, privacy)| {
+            Store::map_group_to_group_response(
+                Principal::from_slice(&[id]).to_string(),
+                Group {
+                    name: name.to_string(),
+                    tags,
+                    privacy,
+                    ..Default::default()
+                },
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+fn test_matching_identifiers(
+    groups: &[GroupResponse],
+    expression: &GroupFilterExpression,
+) -> Vec<Principal> {
+    groups
+        .iter()
+        .filter(|group| Store::matches_expression(group, expression, &HashMap::new()))
+        .map(|group| group.identifier)
+        .collect()
+}
+
+// `(tag 3 OR tag 7) AND NOT name "old"` combined with the flat filters
+#[test]
+pub fn filter_expression() {
+    use ic_scalable_canister::ic_scalable_misc::enums::filter_type::FilterType;
+
+    let groups = test_groups(vec![
+        (1, "new", vec![3], Privacy::Public),
+        (2, "old", vec![7], Privacy::Public),
+        (3, "new", vec![7], Privacy::Public),
+        (4, "new", vec![5], Privacy::Public),
+    ]);
+    let expression = GroupFilterExpression::And(vec![
+        GroupFilterExpression::Or(vec![
+            GroupFilterExpression::Filter(GroupFilter::Tag(3)),
+            GroupFilterExpression::Filter(GroupFilter::Tag(7)),
+        ]),
+        GroupFilterExpression::Not(Box::new(GroupFilterExpression::Filter(GroupFilter::Name(
+            "old".to_string(),
+        )))),
+    ]);
+    let id = |id: u8| Principal::from_slice(&[id]);
+
+    let combined = GroupFilterExpression::combine(vec![], FilterType::Or, Some(expression.clone()));
+    assert_eq!(
+        test_matching_identifiers(&groups, &combined),
+        vec![id(1), id(3)]
+    );
+
+    let combined = GroupFilterExpression::combine(
+        vec![GroupFilter::Tag(7)],
+        FilterType::And,
+        Some(expression),
+    );
+    assert_eq!(test_matching_identifiers(&groups, &combined), vec![id(3)]);
+
+    let combined = GroupFilterExpression::combine(vec![], FilterType::Or, None);
+    assert_eq!(test_matching_identifiers(&groups, &combined).len(), 4);
+    assert!(test_matching_identifiers(&groups, &GroupFilterExpression::Or(vec![])).is_empty());
+}
+
+// Invite only groups are left out, unless they are included or explicitly filtered on
+#[test]
+pub fn privacy_policy() {
+    use shared::group_model::PrivacyFilter;
+
+    let groups = test_groups(vec![
+        (1, "public", vec![], Privacy::Public),
+        (2, "invite", vec![], Privacy::InviteOnly),
+        (3, "private", vec![], Privacy::Private),
+    ]);
+    let id = |id: u8| Principal::from_slice(&[id]);
+    let invite_only =
+        GroupFilterExpression::Filter(GroupFilter::Privacy(PrivacyFilter::InviteOnly));
+
+    let expression = GroupFilterExpression::And(vec![]).with_privacy_policy(false);
+    assert_eq!(
+        test_matching_identifiers(&groups, &expression),
+        vec![id(1), id(3)]
+    );
+
+    let expression = GroupFilterExpression::And(vec![]).with_privacy_policy(true);
+    assert_eq!(
+        test_matching_identifiers(&groups, &expression),
+        vec![id(1), id(2), id(3)]
+    );
+
+    let expression = GroupFilterExpression::Or(vec![
+        invite_only.clone(),
+        GroupFilterExpression::Filter(GroupFilter::Privacy(PrivacyFilter::Public)),
+    ])
+    .with_privacy_policy(false);
+    assert_eq!(
+        test_matching_identifiers(&groups, &expression),
+        vec![id(1), id(2)]
+    );
+
+    // A negated filter does not ask for invite only groups
+    let expression = GroupFilterExpression::Not(Box::new(invite_only)).with_privacy_policy(false);
+    assert_eq!(
+        test_matching_identifiers(&groups, &expression),
+        vec![id(1), id(3)]
+    );
+}
@@ -0,0 +1,132 @@
+use candid::Principal;
+use ic_cdk::api::time;
+use shared::group_model::{Group, GroupVersion, UpdateGroup};
+
+use crate::store::GROUP_VERSIONS;
+
+// Max number of versions that are kept per group, the oldest versions are removed first
+static MAX_GROUP_VERSIONS: usize = 20;
+
+// History of the profile fields of the groups, a version is added on every profile edit
+// the keys are `{group_identifier}:{version}`, zero padded so the versions of a group are ordered
+pub struct GroupVersions;
+
+impl GroupVersions {
+    // Method to add the edited profile as a new version
+    // the profile from before the edit is added first if the group has no versions yet, so the original can be restored
+    pub fn add_version(
+        caller: Principal,
+        group_identifier: Principal,
+        previous_group_data: &Group,
+        group_data: &Group,
+    ) {
+        if Self::get_versions(group_identifier).is_empty() {
+            Self::insert_version(
+                group_identifier,
+                Self::get_profile(previous_group_data),
+                previous_group_data.created_by,
+                previous_group_data.updated_on,
+            );
+        }
+
+        Self::insert_version(
+            group_identifier,
+            Self::get_profile(group_data),
+            caller,
+            time(),
+        );
+        Self::remove_old_versions(group_identifier);
+    }
+
+    // Method to get the versions of a group, newest first
+    pub fn get_versions(group_identifier: Principal) -> Vec<GroupVersion> {
+        let prefix = format!("{}:", group_identifier);
+        let mut versions: Vec<GroupVersion> = GROUP_VERSIONS.with(|versions| {
+            versions
+                .borrow()
+                .range(prefix.clone()..)
+                .take_while(|(key, _)| key.starts_with(&prefix))
+                .map(|(_, version)| version)
+                .collect()
+        });
+
+        versions.reverse();
+        versions
+    }
+
+    pub fn get_version(group_identifier: Principal, version: u64) -> Option<GroupVersion> {
+        GROUP_VERSIONS.with(|versions| {
+            versions
+                .borrow()
+                .get(&Self::get_key(group_identifier, version))
+        })
+    }
+
+    // Method to remove all versions of a group, used when the group is permanently removed
+    pub fn remove_versions(group_identifier: Principal) {
+        for version in Self::get_versions(group_identifier) {
+            GROUP_VERSIONS.with(|versions| {
+                versions
+                    .borrow_mut()
+                    .remove(&Self::get_key(group_identifier, version.version))
+            });
+        }
+    }
+
+    fn insert_version(
+        group_identifier: Principal,
+        profile: UpdateGroup,
+        edited_by: Principal,
+        edited_on: u64,
+    ) {
+        let version = Self::get_versions(group_identifier)
+            .first()
+            .map(|version| version.version + 1)
+            .unwrap_or(1);
+
+        GROUP_VERSIONS.with(|versions| {
+            versions.borrow_mut().insert(
+                Self::get_key(group_identifier, version),
+                GroupVersion {
+                    version,
+                    group_identifier,
+                    profile,
+                    edited_by,
+                    edited_on,
+                },
+            )
+        });
+    }
+
+    fn remove_old_versions(group_identifier: Principal) {
+        for version in Self::get_versions(group_identifier)
+            .iter()
+            .skip(MAX_GROUP_VERSIONS)
+        {
+            GROUP_VERSIONS.with(|versions| {
+                versions
+                    .borrow_mut()
+                    .remove(&Self::get_key(group_identifier, version.version))
+            });
+        }
+    }
+
+    // Method to get the profile fields of a group, these are the fields that can be changed with `update_group`
+    fn get_profile(group: &Group) -> UpdateGroup {
+        UpdateGroup {
+            name: group.name.clone(),
+            description: group.description.clone(),
+            website: group.website.clone(),
+            location: group.location.clone(),
+            privacy: group.privacy.clone(),
+            image: group.image.clone(),
+            privacy_gated_type_amount: group.privacy_gated_type_amount,
+            banner_image: group.banner_image.clone(),
+            tags: group.tags.clone(),
+        }
+    }
+
+    fn get_key(group_identifier: Principal, version: u64) -> String {
+        format!("{}:{:020}", group_identifier, version)
+    }
+}
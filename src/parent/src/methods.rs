@@ -1,26 +1,64 @@
 use candid::Principal;
 use ic_cdk::query;
 use ic_scalable_misc::{
-    enums::filter_type::FilterType,
+    enums::{api_error_type::ApiError, filter_type::FilterType},
     models::{identifier_model::Identifier, paged_response_models::PagedResponse},
 };
 
-use shared::group_model::{GroupFilter, GroupResponse, GroupSort};
+use shared::group_model::{
+    GroupCursorPage, GroupFilter, GroupFilterExpression, GroupResponse, GroupSort,
+};
 
 use super::store::ScalableData;
 
 // Method used to get all the groups from the child canisters filtered, sorted and paged
-// requires composite queries to be released to mainnet
-// #[query(composite = true)]
-#[query]
+// the child canisters are queried in parallel through a composite query
+// the optional filter expression allows nested and / or / not filters, groups need to match both the filters and the expression
+// invite only groups are left out unless `include_invite_only` is true or the filters explicitly ask for them
+#[query(composite = true)]
 async fn get_groups(
     limit: usize,
     page: usize,
     filters: Vec<GroupFilter>,
     filter_type: FilterType,
     sort: GroupSort,
+    filter_expression: Option<GroupFilterExpression>,
+    include_invite_only: Option<bool>,
 ) -> PagedResponse<GroupResponse> {
-    ScalableData::get_child_canister_data(limit, page, filters, filter_type, sort).await
+    ScalableData::get_child_canister_data(
+        limit,
+        page,
+        filters,
+        filter_type,
+        sort,
+        filter_expression,
+        include_invite_only,
+    )
+    .await
+}
+
+// Method used to get the groups from the child canisters filtered and sorted with cursor based pagination
+// pass the `next_cursor` of a page to get the next page, the child canisters are queried in parallel
+#[query(composite = true)]
+async fn get_groups_by_cursor(
+    limit: usize,
+    cursor: Option<Vec<u8>>,
+    filters: Vec<GroupFilter>,
+    filter_type: FilterType,
+    sort: GroupSort,
+    filter_expression: Option<GroupFilterExpression>,
+    include_invite_only: Option<bool>,
+) -> Result<GroupCursorPage, ApiError> {
+    ScalableData::get_child_canister_data_by_cursor(
+        limit,
+        cursor,
+        filters,
+        filter_type,
+        sort,
+        filter_expression,
+        include_invite_only,
+    )
+    .await
 }
 
 #[query]
@@ -1,6 +1,7 @@
 use std::{cell::RefCell, collections::HashMap};
 
 use candid::{CandidType, Deserialize, Principal};
+use futures::future::join_all;
 use ic_cdk::{
     api::{call, time},
     id,
@@ -11,15 +12,13 @@ use ic_scalable_misc::{
         api_error_type::{ApiError, ApiErrorType},
         canister_type::CanisterType,
         filter_type::FilterType,
-        sort_type::SortDirection,
         wasm_version_type::WasmVersion,
     },
     helpers::{
         canister_helper::{Canister, CanisterID, CanisterSettings, InstallCodeMode},
         error_helper::api_error,
         logger_helper::add_log,
-        paging_helper::get_paged_data,
-        serialize_helper::deserialize,
+        serialize_helper::{deserialize, serialize},
     },
     models::{
         canister_models::ScalableCanisterDetails,
@@ -29,7 +28,10 @@ use ic_scalable_misc::{
     },
 };
 
-use shared::group_model::{GroupFilter, GroupResponse, GroupSort};
+use shared::group_model::{
+    GroupChunkQuery, GroupCursor, GroupCursorPage, GroupFilter, GroupFilterExpression,
+    GroupResponse, GroupSort, GroupSortKey, SortedGroups,
+};
 
 #[derive(CandidType, Clone, Deserialize)]
 pub struct ScalableMetaData {
@@ -442,107 +444,300 @@ impl ScalableData {
     }
 
     // Method used to get all the groups from the child canisters filtered, sorted and paged
-    // requires composite queries to be released to mainnet
+    // should be called from a composite query so the child canisters can be queried in parallel
     pub async fn get_child_canister_data(
         limit: usize,
         page: usize,
         filters: Vec<GroupFilter>,
         filter_type: FilterType,
         sort: GroupSort,
+        filter_expression: Option<GroupFilterExpression>,
+        include_invite_only: Option<bool>,
     ) -> PagedResponse<GroupResponse> {
+        let expression = GroupFilterExpression::combine(filters, filter_type, filter_expression)
+            .with_privacy_policy(include_invite_only.unwrap_or(false));
+
         let canisters: Vec<Principal> = DATA.with(|data| {
             data.borrow()
                 .canisters
-                .clone()
-                .into_iter()
-                .map(|c| c.1.principal.clone())
+                .values()
+                .map(|c| c.principal)
                 .collect()
         });
 
-        // Create a vector to store the groups
-        let mut groups: Vec<GroupResponse> = vec![];
+        // Only the groups up to and including the requested page are needed from each child canister
+        let query = GroupChunkQuery {
+            sort: sort.clone(),
+            cursor: None,
+            limit: page.saturating_mul(limit),
+        };
 
-        // Get the data from each child canister
-        for canister in canisters {
-            let mut canister_data =
-                Self::get_filtered_child_data(canister, &filters, &filter_type).await;
-            groups.append(&mut canister_data);
-        }
+        // Get the sorted groups from all the child canisters at once
+        let canister_data = join_all(
+            canisters
+                .into_iter()
+                .map(|canister| Self::get_sorted_child_data(canister, &expression, &query)),
+        )
+        .await;
+
+        let total = canister_data.iter().map(|data| data.total).sum();
+        let groups = Self::merge_sorted_groups(
+            &sort,
+            canister_data.into_iter().map(|data| data.groups).collect(),
+            query.limit,
+        );
 
-        // Sort the groups
-        let ordered_groups = Self::get_ordered_groups(groups, sort);
-        get_paged_data(ordered_groups, limit, page)
+        Self::get_paged_groups(groups, total, limit, page)
     }
 
-    // Method to get the data from a single child canister in chunks
-    // requires composite queries to be released to mainnet
-    async fn get_filtered_child_data(
-        canister_principal: Principal,
-        filters: &Vec<GroupFilter>,
-        filter_type: &FilterType,
-    ) -> Vec<GroupResponse> {
-        // Do initial fetch of the first chunk and determine the number of chunks
-        let (mut bytes, (_, last)) =
-            Self::get_chunked_child_data(canister_principal, filters, filter_type, 0, None).await;
+    // Method to page the merged groups, uses the same paging rules as `get_paged_data`
+    // but the total is passed along because the groups after the requested page are not fetched
+    fn get_paged_groups(
+        groups: Vec<(GroupSortKey, GroupResponse)>,
+        total: usize,
+        limit: usize,
+        page: usize,
+    ) -> PagedResponse<GroupResponse> {
+        if page == 0 {
+            return PagedResponse {
+                page,
+                limit,
+                total,
+                number_of_pages: 0,
+                data: vec![],
+            };
+        }
 
-        // If there are more chunks, fetch them
-        if last > 1 {
-            for i in 1..last + 1 {
-                let (mut _bytes, _) =
-                    Self::get_chunked_child_data(canister_principal, filters, filter_type, i, None)
-                        .await;
-                // Append the bytes to the first chunk
-                bytes.append(&mut _bytes);
+        let limit = limit.min(total);
+        let number_of_pages = match limit {
+            0 => 0,
+            _ => total.div_ceil(limit),
+        };
+        let page = page.min(number_of_pages);
+
+        let data = groups
+            .into_iter()
+            .skip(page.saturating_sub(1).saturating_mul(limit))
+            .take(limit)
+            .map(|(_, group)| group)
+            .collect();
+
+        PagedResponse {
+            page,
+            limit,
+            total,
+            number_of_pages,
+            data,
+        }
+    }
+
+    // Method to merge the sorted groups of the child canisters (k-way merge) until `limit` groups are taken
+    fn merge_sorted_groups(
+        sort: &GroupSort,
+        canister_groups: Vec<Vec<(GroupSortKey, GroupResponse)>>,
+        limit: usize,
+    ) -> Vec<(GroupSortKey, GroupResponse)> {
+        let mut canister_groups: Vec<_> = canister_groups
+            .into_iter()
+            .map(|groups| groups.into_iter().peekable())
+            .collect();
+
+        let mut merged_groups = vec![];
+        while merged_groups.len() < limit {
+            // Take the first group of the child canister that has the next group in order
+            let next = canister_groups
+                .iter_mut()
+                .enumerate()
+                .filter_map(|(index, groups)| groups.peek().map(|group| (index, group)))
+                .min_by(|(_, (a_key, a)), (_, (b_key, b))| {
+                    sort.compare((a_key, &a.identifier), (b_key, &b.identifier))
+                })
+                .map(|(index, _)| index);
+
+            match next.and_then(|index| canister_groups[index].next()) {
+                None => break,
+                Some(group) => merged_groups.push(group),
             }
         }
 
+        merged_groups
+    }
+
+    // Method used to get the groups from the child canisters filtered and sorted with cursor based (keyset) pagination
+    // every child canister only returns the next `limit` groups after the cursor, so deep pages are as cheap as the first page
+    pub async fn get_child_canister_data_by_cursor(
+        limit: usize,
+        cursor: Option<Vec<u8>>,
+        filters: Vec<GroupFilter>,
+        filter_type: FilterType,
+        sort: GroupSort,
+        filter_expression: Option<GroupFilterExpression>,
+        include_invite_only: Option<bool>,
+    ) -> Result<GroupCursorPage, ApiError> {
+        let inputs = Some(vec![
+            format!("limit - {}", &limit),
+            format!("sort - {:?}", &sort),
+        ]);
+
+        // Decode the opaque cursor and check if it belongs to the requested sort
+        let cursor = match cursor {
+            None => None,
+            Some(_cursor) => match Self::decode_cursor(&sort, _cursor) {
+                Some(_cursor) => Some(_cursor),
+                None => {
+                    return Err(api_error(
+                        ApiErrorType::BadRequest,
+                        "INVALID_CURSOR",
+                        "The cursor is invalid or does not match the sort",
+                        &Self::get_name(),
+                        "get_child_canister_data_by_cursor",
+                        inputs,
+                    ))
+                }
+            },
+        };
+
+        let canisters: Vec<Principal> = DATA.with(|data| {
+            data.borrow()
+                .canisters
+                .values()
+                .map(|c| c.principal)
+                .collect()
+        });
+
+        let expression = GroupFilterExpression::combine(filters, filter_type, filter_expression)
+            .with_privacy_policy(include_invite_only.unwrap_or(false));
+        let query = GroupChunkQuery {
+            sort: sort.clone(),
+            cursor,
+            limit,
+        };
+
+        // Get the next groups after the cursor from all the child canisters at once
+        let canister_data = join_all(
+            canisters
+                .into_iter()
+                .map(|canister| Self::get_sorted_child_data(canister, &expression, &query)),
+        )
+        .await;
+
+        Ok(Self::get_cursor_page(&sort, canister_data, limit))
+    }
+
+    // Method to decode an opaque cursor, `None` if it is invalid or does not belong to the sort
+    fn decode_cursor(sort: &GroupSort, cursor: Vec<u8>) -> Option<GroupCursor> {
+        match deserialize::<GroupCursor>(cursor) {
+            Ok(_cursor) if sort.is_sort_key(&_cursor.sort_key) => Some(_cursor),
+            _ => None,
+        }
+    }
+
+    // Method to merge the next groups of the child canisters into a page with the cursor for the next page
+    fn get_cursor_page(
+        sort: &GroupSort,
+        canister_data: Vec<SortedGroups>,
+        limit: usize,
+    ) -> GroupCursorPage {
+        let total = canister_data.iter().map(|data| data.total).sum();
+        let child_has_more = canister_data.iter().any(|data| data.has_more);
+        let returned: usize = canister_data.iter().map(|data| data.groups.len()).sum();
+
+        // Merge the groups of the child canisters and only keep the first `limit` groups
+        let groups = Self::merge_sorted_groups(
+            sort,
+            canister_data.into_iter().map(|data| data.groups).collect(),
+            limit,
+        );
+        let has_more = child_has_more || returned > groups.len();
+
+        // The cursor for the next page points to the last group of this page
+        let next_cursor = match groups.last() {
+            Some((_sort_key, _group)) if has_more => serialize(&GroupCursor {
+                sort_key: _sort_key.clone(),
+                identifier: _group.identifier,
+            })
+            .ok(),
+            _ => None,
+        };
+
+        GroupCursorPage {
+            limit,
+            total,
+            data: groups.into_iter().map(|(_, group)| group).collect(),
+            next_cursor,
+        }
+    }
+
+    // Method to get the next sorted groups after the query cursor from a single child canister in chunks
+    async fn get_sorted_child_data(
+        canister_principal: Principal,
+        expression: &GroupFilterExpression,
+        query: &GroupChunkQuery,
+    ) -> SortedGroups {
+        let bytes = Self::get_child_bytes(canister_principal, expression, Some(query)).await;
+
         // Deserialize the bytes to the correct data type
-        match deserialize::<Vec<GroupResponse>>(bytes.clone()) {
+        match deserialize::<SortedGroups>(bytes) {
             Ok(_res) => _res,
             Err(_err) => {
                 ic_cdk::println!("Error: {}", _err);
-                vec![]
+                SortedGroups {
+                    total: 0,
+                    has_more: false,
+                    groups: vec![],
+                }
             }
         }
     }
 
-    // Method to get ordered groups
-    fn get_ordered_groups(mut groups: Vec<GroupResponse>, sort: GroupSort) -> Vec<GroupResponse> {
-        match sort {
-            GroupSort::CreatedOn(direction) => match direction {
-                SortDirection::Asc => groups.sort_by(|a, b| a.created_on.cmp(&b.created_on)),
-                SortDirection::Desc => groups.sort_by(|a, b| b.created_on.cmp(&a.created_on)),
-            },
-            GroupSort::UpdatedOn(direction) => match direction {
-                SortDirection::Asc => groups.sort_by(|a, b| a.updated_on.cmp(&b.updated_on)),
-                SortDirection::Desc => groups.sort_by(|a, b| b.updated_on.cmp(&a.updated_on)),
-            },
-            GroupSort::Name(direction) => match direction {
-                SortDirection::Asc => groups.sort_by(|a, b| a.name.cmp(&b.name)),
-                SortDirection::Desc => groups.sort_by(|a, b| b.name.cmp(&a.name)),
-            },
-            GroupSort::MemberCount(direction) => match direction {
-                SortDirection::Asc => groups.sort_by(|a, b| a.member_count.cmp(&b.member_count)),
-                SortDirection::Desc => groups.sort_by(|a, b| b.member_count.cmp(&a.member_count)),
-            },
-        };
-        groups
+    // Method to get all the chunks of serialized data from a single child canister
+    async fn get_child_bytes(
+        canister_principal: Principal,
+        expression: &GroupFilterExpression,
+        query: Option<&GroupChunkQuery>,
+    ) -> Vec<u8> {
+        // Do initial fetch of the first chunk and determine the number of chunks
+        let (mut bytes, (_, last)) =
+            Self::get_chunked_child_data(canister_principal, expression, 0, None, query).await;
+
+        // If there are more chunks, fetch them all at once
+        if last > 1 {
+            let chunks = join_all((1..last + 1).map(|i| {
+                Self::get_chunked_child_data(canister_principal, expression, i, None, query)
+            }))
+            .await;
+
+            // Append the bytes to the first chunk, `join_all` keeps the order of the chunks
+            for (mut _bytes, _) in chunks {
+                bytes.append(&mut _bytes);
+            }
+        }
+
+        bytes
     }
 
     // Inter canister call to fetch the chunked data from the child canister
+    // the flat filters are already part of the expression, so only the expression is passed along
     async fn get_chunked_child_data(
         canister_principal: Principal,
-        filters: &Vec<GroupFilter>,
-        filter_type: &FilterType,
+        expression: &GroupFilterExpression,
         chunk: usize,
         max_bytes_per_chunk: Option<usize>,
+        query: Option<&GroupChunkQuery>,
     ) -> (Vec<u8>, (usize, usize)) {
         // If the max bytes per chunk is not provided, use the default of 2_000_000 (2mb)
         let _max_bytes_per_chunk = max_bytes_per_chunk.unwrap_or(2_000_000);
         let result: Result<(Vec<u8>, (usize, usize)), _> = call::call(
             canister_principal,
             "get_chunked_data",
-            (filters, filter_type, chunk, _max_bytes_per_chunk),
+            (
+                Vec::<GroupFilter>::new(),
+                FilterType::And,
+                chunk,
+                _max_bytes_per_chunk,
+                query,
+                Some(expression),
+            ),
         )
         .await;
 
@@ -558,3 +753,190 @@ impl ScalableData {
         DATA.with(|v| v.borrow().name.clone())
     }
 }
+
+// Group with only the fields that are used for sorting and paging
+#[cfg(test)]
+fn test_group(id: u8, member_count: usize) -> (GroupSortKey, GroupResponse) {
+    let identifier = Principal::from_slice(&[id]);
+    let group = GroupResponse {
+        identifier,
+        name: format!("group {}", id),
+        description: Default::default(),
+        website: Default::default(),
+        location: Default::default(),
+        privacy: Default::default(),
+        created_by: identifier,
+        owner: identifier,
+        matrix_space_id: Default::default(),
+        image: Default::default(),
+        banner_image: Default::default(),
+        tags: vec![],
+        roles: vec![],
+        member_count,
+        wallets: vec![],
+        is_deleted: false,
+        privacy_gated_type_amount: None,
+        pending_ownership_transfer: None,
+        deleted_on: None,
+        revision: 0,
+        updated_on: 0,
+        created_on: 0,
+    };
+    (GroupSortKey::MemberCount(member_count), group)
+}
+
+// Next `limit` groups after the cursor of a single child canister, like the child canister returns them
+#[cfg(test)]
+fn test_child_groups(
+    sort: &GroupSort,
+    mut groups: Vec<(GroupSortKey, GroupResponse)>,
+    cursor: Option<&GroupCursor>,
+    limit: usize,
+) -> SortedGroups {
+    let total = groups.len();
+    groups.sort_by(|(a_key, a), (b_key, b)| {
+        sort.compare((a_key, &a.identifier), (b_key, &b.identifier))
+    });
+    let mut groups: Vec<_> = groups
+        .into_iter()
+        .filter(|(_sort_key, _group)| {
+            cursor.is_none_or(|_cursor| {
+                sort.compare(
+                    (_sort_key, &_group.identifier),
+                    (&_cursor.sort_key, &_cursor.identifier),
+                ) == std::cmp::Ordering::Greater
+            })
+        })
+        .collect();
+    let has_more = groups.len() > limit;
+    groups.truncate(limit);
+
+    SortedGroups {
+        total,
+        has_more,
+        groups,
+    }
+}
+
+#[cfg(test)]
+fn test_identifiers(groups: &[GroupResponse]) -> Vec<Principal> {
+    groups.iter().map(|group| group.identifier).collect()
+}
+
+// Groups with the same sort key on different child canisters are merged in identifier order
+#[test]
+pub fn merge_sorted_groups() {
+    use ic_scalable_misc::enums::sort_type::SortDirection;
+
+    let sort = GroupSort::MemberCount(SortDirection::Desc);
+    let canister_groups = vec![
+        vec![test_group(2, 10), test_group(4, 5)],
+        vec![test_group(1, 10), test_group(3, 10), test_group(5, 1)],
+        vec![],
+    ];
+
+    let merged = ScalableData::merge_sorted_groups(&sort, canister_groups.clone(), 10);
+    let identifiers: Vec<Principal> = merged.iter().map(|(_, group)| group.identifier).collect();
+    let expected: Vec<Principal> = [1, 2, 3, 4, 5]
+        .iter()
+        .map(|id| Principal::from_slice(&[*id]))
+        .collect();
+    assert_eq!(identifiers, expected);
+
+    let merged = ScalableData::merge_sorted_groups(&sort, canister_groups, 2);
+    let identifiers: Vec<Principal> = merged.iter().map(|(_, group)| group.identifier).collect();
+    assert_eq!(identifiers, expected[..2]);
+
+    assert!(ScalableData::merge_sorted_groups(&sort, vec![], 10).is_empty());
+}
+
+// Pages past the end are clamped to the last page and the limit is clamped to the total
+#[test]
+pub fn get_paged_groups() {
+    let groups: Vec<_> = (1..=5).map(|id| test_group(id, 0)).collect();
+
+    let paged = ScalableData::get_paged_groups(groups.clone(), 5, 2, 1);
+    assert_eq!(paged.number_of_pages, 3);
+    assert_eq!(
+        test_identifiers(&paged.data),
+        test_identifiers(&[groups[0].1.clone(), groups[1].1.clone()])
+    );
+
+    let paged = ScalableData::get_paged_groups(groups.clone(), 5, 2, 10);
+    assert_eq!(paged.page, 3);
+    assert_eq!(test_identifiers(&paged.data), vec![groups[4].1.identifier]);
+
+    let paged = ScalableData::get_paged_groups(groups.clone(), 5, 20, 1);
+    assert_eq!(paged.limit, 5);
+    assert_eq!(paged.number_of_pages, 1);
+    assert_eq!(paged.data.len(), 5);
+
+    let paged = ScalableData::get_paged_groups(groups.clone(), 5, 2, 0);
+    assert_eq!(paged.number_of_pages, 0);
+    assert!(paged.data.is_empty());
+
+    let paged = ScalableData::get_paged_groups(vec![], 0, 2, 1);
+    assert_eq!(paged.page, 0);
+    assert_eq!(paged.number_of_pages, 0);
+    assert!(paged.data.is_empty());
+}
+
+// Following the cursor returns every group of every child canister once and in order
+#[test]
+pub fn cursor_round_trip() {
+    use ic_scalable_misc::enums::sort_type::SortDirection;
+
+    let sort = GroupSort::MemberCount(SortDirection::Desc);
+    let canisters = [
+        vec![test_group(1, 10), test_group(2, 10), test_group(3, 4)],
+        vec![test_group(4, 10), test_group(5, 7), test_group(6, 4)],
+        vec![test_group(7, 1)],
+    ];
+    let expected: Vec<Principal> = [1, 2, 4, 5, 3, 6, 7]
+        .iter()
+        .map(|id| Principal::from_slice(&[*id]))
+        .collect();
+
+    for limit in [1, 2, 3, 7, 10] {
+        let mut cursor = None;
+        let mut identifiers = vec![];
+        loop {
+            let canister_data = canisters
+                .iter()
+                .map(|groups| test_child_groups(&sort, groups.clone(), cursor.as_ref(), limit))
+                .collect();
+            let page = ScalableData::get_cursor_page(&sort, canister_data, limit);
+            assert!(page.data.len() <= limit);
+            identifiers.extend(test_identifiers(&page.data));
+
+            match page.next_cursor {
+                None => break,
+                Some(_cursor) => cursor = ScalableData::decode_cursor(&sort, _cursor),
+            }
+            assert!(cursor.is_some());
+        }
+        assert_eq!(identifiers, expected);
+    }
+
+    // A child canister that truncates its groups has more groups, even if the page is not full
+    let page = ScalableData::get_cursor_page(
+        &sort,
+        vec![SortedGroups {
+            total: 3,
+            has_more: true,
+            groups: vec![test_group(1, 10)],
+        }],
+        2,
+    );
+    assert_eq!(page.data.len(), 1);
+    assert!(page.next_cursor.is_some());
+
+    // A cursor of another sort is rejected
+    let cursor = serialize(&GroupCursor {
+        sort_key: GroupSortKey::Name("group 1".to_string()),
+        identifier: Principal::from_slice(&[1]),
+    })
+    .unwrap();
+    assert!(ScalableData::decode_cursor(&sort, cursor).is_none());
+    assert!(ScalableData::decode_cursor(&sort, vec![0, 1, 2]).is_none());
+}
@@ -1,9 +1,13 @@
-use std::{borrow::Cow, collections::HashMap};
+use std::{borrow::Cow, cmp::Ordering, collections::HashMap};
 
 use candid::{CandidType, Decode, Deserialize, Encode, Principal};
 use ic_scalable_misc::{
     enums::{
-        asset_type::Asset, location_type::Location, privacy_type::Privacy, sort_type::SortDirection,
+        asset_type::Asset,
+        filter_type::FilterType,
+        location_type::Location,
+        privacy_type::{GatedType, NeuronGatedRules, Privacy},
+        sort_type::SortDirection,
     },
     models::{date_models::DateRange, group_role::GroupRole},
     traits::stable_storage_trait::StableStorableTrait,
@@ -29,6 +33,10 @@ pub struct Group {
     pub is_deleted: bool,
     pub member_count: HashMap<Principal, usize>,
     pub wallets: HashMap<Principal, String>,
+    pub pending_ownership_transfer: Option<OwnershipTransferOffer>,
+    pub deleted_on: Option<u64>,
+    // Bumped on every change, used to reject changes that are based on an outdated version of the group
+    pub revision: Option<u64>,
     pub updated_on: u64,
     pub created_on: u64,
 }
@@ -68,10 +76,284 @@ impl Default for Group {
             updated_on: Default::default(),
             created_on: Default::default(),
             privacy_gated_type_amount: Default::default(),
+            pending_ownership_transfer: Default::default(),
+            deleted_on: Default::default(),
+            revision: Default::default(),
         }
     }
 }
 
+// How long soft-deleted groups are kept, the restore period can't be longer than the retention period
+#[derive(Clone, CandidType, Serialize, Deserialize, Debug)]
+pub struct DeletionPolicy {
+    // Time after the deletion in which the owner can restore the group
+    pub restore_period_seconds: u64,
+    // Time after the deletion after which the group is permanently removed
+    pub retention_period_seconds: u64,
+}
+
+impl Storable for DeletionPolicy {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Default for DeletionPolicy {
+    fn default() -> Self {
+        Self {
+            restore_period_seconds: 30 * 24 * 60 * 60,
+            retention_period_seconds: 90 * 24 * 60 * 60,
+        }
+    }
+}
+
+// Audit log entry of a change to a group, only the fields that changed are part of the record
+#[derive(Clone, CandidType, Serialize, Deserialize, Debug)]
+pub struct GroupAuditRecord {
+    pub group_identifier: Principal,
+    pub caller: Principal,
+    pub method: String,
+    pub changes: Vec<GroupFieldChange>,
+    pub timestamp: u64,
+}
+
+impl Storable for GroupAuditRecord {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// Value of a group field before and after a change, `None` if the group didn't exist before or after the change
+#[derive(Clone, CandidType, Serialize, Deserialize, Debug)]
+pub struct GroupFieldChange {
+    pub field: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+// Earlier version of the profile fields of a group, `edited_by` and `edited_on` are from the edit that created the version
+#[derive(Clone, CandidType, Deserialize, Debug)]
+pub struct GroupVersion {
+    pub version: u64,
+    pub group_identifier: Principal,
+    pub profile: UpdateGroup,
+    pub edited_by: Principal,
+    pub edited_on: u64,
+}
+
+impl Storable for GroupVersion {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// Role that is assigned to a member through the group canister until `expires_on`, the role is revoked once it expires
+#[derive(Clone, CandidType, Serialize, Deserialize, Debug)]
+pub struct RoleGrant {
+    pub group_identifier: Principal,
+    pub member_identifier: Principal,
+    pub role_name: String,
+    pub granted_by: Principal,
+    pub expires_on: u64,
+    pub created_on: u64,
+}
+
+impl Storable for RoleGrant {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// Shareable code to join an invite only or private group, the code can be used `max_uses` times until `expires_on` if set
+#[derive(Clone, CandidType, Serialize, Deserialize, Debug)]
+pub struct GroupInvite {
+    pub code: String,
+    pub group_identifier: Principal,
+    pub role_name: Option<String>,
+    pub max_uses: Option<u64>,
+    pub uses: u64,
+    pub expires_on: Option<u64>,
+    pub created_by: Principal,
+    // Rank of the highest role of the creator, the role of the invite needs to stay ranked below it
+    pub created_by_rank: Option<u64>,
+    pub created_on: u64,
+}
+
+impl Storable for GroupInvite {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// Proof that `principal` redeemed an invite code, the member canister checks the signature with `verify_join_grant` on the group canister
+#[derive(Clone, CandidType, Serialize, Deserialize, Debug)]
+pub struct JoinGrant {
+    pub group_identifier: Principal,
+    pub principal: Principal,
+    pub role_name: String,
+    pub invite_code: String,
+    pub issued_on: u64,
+    pub expires_on: u64,
+    pub signature: String,
+}
+
+// Result of the gates of a group for a principal, the principal is eligible if `passed_amount` is at least `required_amount`
+// groups that are not gated have no requirements, invite only groups still need an invite to join
+#[derive(Clone, CandidType, Serialize, Deserialize, Debug)]
+pub struct JoinEligibility {
+    pub group_identifier: Principal,
+    pub privacy: Privacy,
+    pub is_eligible: bool,
+    pub required_amount: u64,
+    pub passed_amount: u64,
+    pub requirements: Vec<JoinRequirement>,
+}
+
+#[derive(Clone, CandidType, Serialize, Deserialize, Debug)]
+pub enum JoinRequirement {
+    Token(TokenRequirement),
+    Neuron(NeuronRequirement),
+    // The group can only be joined with an invite (see `redeem_invite`), this requirement is never met by the check itself
+    Invite,
+}
+
+// Balance of the principal on a token or NFT canister, `error` is set if the balance couldn't be checked
+#[derive(Clone, CandidType, Serialize, Deserialize, Debug)]
+pub struct TokenRequirement {
+    pub canister: Principal,
+    pub standard: String,
+    pub required_amount: u64,
+    pub balance: u128,
+    pub is_met: bool,
+    pub error: Option<String>,
+}
+
+// Neurons of the principal on a governance canister, the requirement is met if a neuron passes all the rules
+#[derive(Clone, CandidType, Serialize, Deserialize, Debug)]
+pub struct NeuronRequirement {
+    pub governance_canister: Principal,
+    pub rules: Vec<NeuronGatedRules>,
+    pub neurons: Vec<NeuronEligibility>,
+    pub is_met: bool,
+    pub error: Option<String>,
+}
+
+#[derive(Clone, CandidType, Serialize, Deserialize, Debug)]
+pub struct NeuronEligibility {
+    pub neuron_id: Vec<u8>,
+    pub stake_e8s: u64,
+    pub created_timestamp_seconds: u64,
+    pub dissolve_delay_seconds: Option<u64>,
+    pub when_dissolved_timestamp_seconds: Option<u64>,
+    pub failed_rules: Vec<NeuronGatedRules>,
+}
+
+// Tracked inter-canister call that has to succeed eventually, failed calls are retried until the max attempts are reached
+#[derive(Clone, CandidType, Serialize, Deserialize, Debug)]
+pub struct GroupJob {
+    pub group_identifier: Principal,
+    pub canister: Principal,
+    pub kind: GroupJobKind,
+    pub status: GroupJobStatus,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    pub created_on: u64,
+    pub updated_on: u64,
+}
+
+impl Storable for GroupJob {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(Clone, CandidType, Serialize, Deserialize, Debug)]
+pub enum GroupJobKind {
+    // Let a member or dependent canister know the group is permanently removed, the data of the group can be removed
+    GroupDeleted,
+    // Let a member or dependent canister know the group is deleted but can still be restored, the data of the group needs to be kept
+    GroupSoftDeleted,
+    // Let a member or dependent canister know a deleted group is restored
+    GroupRestored,
+    // Let a member canister know a role is renamed so members keep the role
+    RoleRenamed {
+        old_name: String,
+        new_name: String,
+    },
+    // Let a member canister know a role is removed, members with the role get the fallback role if one is passed
+    RoleRemoved {
+        role_name: String,
+        fallback_role: Option<String>,
+    },
+    // Let a member canister assign a role to a member
+    RoleAssigned {
+        member_identifier: Principal,
+        role_name: String,
+    },
+    // Let a member canister take a role from a member, used when a temporary role expires
+    RoleRevoked {
+        member_identifier: Principal,
+        role_name: String,
+    },
+    // Let a member canister swap the owner role after the owner of the group changed
+    OwnershipTransferred {
+        previous_owner: Principal,
+        new_owner: Principal,
+    },
+}
+
+#[derive(Clone, CandidType, Serialize, Deserialize, Debug, PartialEq)]
+pub enum GroupJobStatus {
+    Pending,
+    Done,
+    Failed,
+}
+
+#[derive(Clone, CandidType, Serialize, Deserialize, Debug)]
+pub struct OwnershipTransferOffer {
+    pub new_owner: Principal,
+    pub new_owner_member_identifier: Principal,
+    pub offered_by: Principal,
+    pub expires_on: u64,
+    pub created_on: u64,
+}
+
 #[derive(Clone, CandidType, Deserialize)]
 pub struct PostGroup {
     pub name: String,
@@ -99,6 +381,23 @@ pub struct UpdateGroup {
     pub tags: Vec<u32>,
 }
 
+// Partial update of a group, only the fields that are passed are changed
+// `privacy_gated_type_amount` is changed when it is passed, `Some(None)` clears the amount
+// if `expected_updated_on` is passed the update is rejected when the group changed after that time
+#[derive(Clone, CandidType, Deserialize, Debug, Default)]
+pub struct PatchGroup {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub website: Option<String>,
+    pub location: Option<Location>,
+    pub privacy: Option<Privacy>,
+    pub image: Option<Asset>,
+    pub privacy_gated_type_amount: Option<Option<u64>>,
+    pub banner_image: Option<Asset>,
+    pub tags: Option<Vec<u32>>,
+    pub expected_updated_on: Option<u64>,
+}
+
 #[derive(Clone, CandidType, Serialize, Deserialize, Debug)]
 pub struct GroupResponse {
     pub identifier: Principal,
@@ -118,6 +417,9 @@ pub struct GroupResponse {
     pub wallets: Vec<(Principal, String)>,
     pub is_deleted: bool,
     pub privacy_gated_type_amount: Option<u64>,
+    pub pending_ownership_transfer: Option<OwnershipTransferOffer>,
+    pub deleted_on: Option<u64>,
+    pub revision: u64,
     pub updated_on: u64,
     pub created_on: u64,
 }
@@ -128,6 +430,103 @@ pub enum GroupSort {
     MemberCount(SortDirection),
     CreatedOn(SortDirection),
     UpdatedOn(SortDirection),
+    // Orders by the match score of the `Search` filters, best match first
+    Relevance,
+    // Orders by the distance to (lat, lng), nearest first, groups without a physical location are last
+    Distance(f64, f64),
+    // Orders by privacy type; public, private, invite only, token gated, neuron gated
+    Privacy(SortDirection),
+}
+
+impl GroupSort {
+    pub fn direction(&self) -> SortDirection {
+        use GroupSort::*;
+        match self {
+            Name(direction)
+            | MemberCount(direction)
+            | CreatedOn(direction)
+            | UpdatedOn(direction)
+            | Privacy(direction) => direction.clone(),
+            Relevance => SortDirection::Desc,
+            Distance(_, _) => SortDirection::Asc,
+        }
+    }
+
+    // Check if a sort key belongs to this sort, used to validate cursors
+    pub fn is_sort_key(&self, sort_key: &GroupSortKey) -> bool {
+        matches!(
+            (self, sort_key),
+            (GroupSort::Name(_), GroupSortKey::Name(_))
+                | (GroupSort::MemberCount(_), GroupSortKey::MemberCount(_))
+                | (GroupSort::CreatedOn(_), GroupSortKey::CreatedOn(_))
+                | (GroupSort::UpdatedOn(_), GroupSortKey::UpdatedOn(_))
+                | (GroupSort::Relevance, GroupSortKey::Relevance(_))
+                | (GroupSort::Distance(_, _), GroupSortKey::Distance(_))
+                | (GroupSort::Privacy(_), GroupSortKey::Privacy(_))
+        )
+    }
+
+    // Compare two groups by their sort key, groups with the same sort key are ordered by their identifier
+    // so the order is the same on every canister and a cursor always points to a single position
+    pub fn compare(
+        &self,
+        (a_key, a_identifier): (&GroupSortKey, &Principal),
+        (b_key, b_identifier): (&GroupSortKey, &Principal),
+    ) -> Ordering {
+        let ordering = a_key.partial_cmp(b_key).unwrap_or(Ordering::Equal);
+        let ordering = match self.direction() {
+            SortDirection::Asc => ordering,
+            SortDirection::Desc => ordering.reverse(),
+        };
+        ordering.then_with(|| a_identifier.cmp(b_identifier))
+    }
+}
+
+// The value a group is sorted on for a specific `GroupSort`
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq, PartialOrd)]
+pub enum GroupSortKey {
+    Name(String),
+    MemberCount(usize),
+    CreatedOn(u64),
+    UpdatedOn(u64),
+    Relevance(u32),
+    // Distance in km, `f64::MAX` for groups without a physical location
+    Distance(f64),
+    Privacy(u8),
+}
+
+// Position of the last group of a page, the next page starts after this group
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+pub struct GroupCursor {
+    pub sort_key: GroupSortKey,
+    pub identifier: Principal,
+}
+
+// Used by the parent canister to get the next `limit` sorted groups after the cursor from a child canister
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct GroupChunkQuery {
+    pub sort: GroupSort,
+    pub cursor: Option<GroupCursor>,
+    pub limit: usize,
+}
+
+// Response of a child canister to a `GroupChunkQuery` (serialized and chunked)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SortedGroups {
+    // total amount of groups that match the filters
+    pub total: usize,
+    // true if there are more groups after the returned groups
+    pub has_more: bool,
+    pub groups: Vec<(GroupSortKey, GroupResponse)>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct GroupCursorPage {
+    pub limit: usize,
+    pub total: usize,
+    pub data: Vec<GroupResponse>,
+    // opaque cursor to pass along to get the next page, `None` if there are no more groups
+    pub next_cursor: Option<Vec<u8>>,
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize)]
@@ -139,4 +538,185 @@ pub enum GroupFilter {
     Tag(u32),
     UpdatedOn(DateRange),
     CreatedOn(DateRange),
+    // Full-text search on the name, description and website, every word needs to match
+    Search(String),
+    // Groups with a physical location within `radius_km` of the coordinates
+    NearLocation {
+        lat: f64,
+        lng: f64,
+        radius_km: f64,
+    },
+    // Groups with a physical location within the box, the box wraps around the antimeridian if `min_lng` > `max_lng`
+    BoundingBox {
+        min_lat: f64,
+        min_lng: f64,
+        max_lat: f64,
+        max_lng: f64,
+    },
+    Privacy(PrivacyFilter),
+}
+
+// Privacy type to filter groups on
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub enum PrivacyFilter {
+    Public,
+    Private,
+    InviteOnly,
+    // `None` matches all gated groups, otherwise only the groups with the gated sub-type
+    Gated(Option<GatedFilter>),
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub enum GatedFilter {
+    Neuron,
+    Token,
+}
+
+impl PrivacyFilter {
+    pub fn matches(&self, privacy: &Privacy) -> bool {
+        matches!(
+            (self, privacy),
+            (PrivacyFilter::Public, Privacy::Public)
+                | (PrivacyFilter::Private, Privacy::Private)
+                | (PrivacyFilter::InviteOnly, Privacy::InviteOnly)
+                | (PrivacyFilter::Gated(None), Privacy::Gated(_))
+                | (
+                    PrivacyFilter::Gated(Some(GatedFilter::Neuron)),
+                    Privacy::Gated(GatedType::Neuron(_))
+                )
+                | (
+                    PrivacyFilter::Gated(Some(GatedFilter::Token)),
+                    Privacy::Gated(GatedType::Token(_))
+                )
+        )
+    }
+}
+
+// Nested filter expression over group filters
+// `(tag 3 OR tag 7) AND NOT owner X` is `And([Or([Filter(Tag(3)), Filter(Tag(7))]), Not(Filter(Owner(X)))])`
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum GroupFilterExpression {
+    Filter(GroupFilter),
+    // Matches if all the expressions match, an empty `And` matches all groups
+    And(Vec<GroupFilterExpression>),
+    // Matches if any of the expressions match, an empty `Or` matches no groups
+    Or(Vec<GroupFilterExpression>),
+    Not(Box<GroupFilterExpression>),
+}
+
+impl GroupFilterExpression {
+    // Map the flat filters to an expression, no filters matches all groups for both filter types
+    pub fn from_filters(filters: Vec<GroupFilter>, filter_type: FilterType) -> Self {
+        let filters = filters
+            .into_iter()
+            .map(GroupFilterExpression::Filter)
+            .collect();
+        match filter_type {
+            FilterType::And => GroupFilterExpression::And(filters),
+            FilterType::Or if filters.is_empty() => GroupFilterExpression::And(filters),
+            FilterType::Or => GroupFilterExpression::Or(filters),
+        }
+    }
+
+    // Combine the flat filters with an optional expression, a group needs to match both
+    pub fn combine(
+        filters: Vec<GroupFilter>,
+        filter_type: FilterType,
+        expression: Option<GroupFilterExpression>,
+    ) -> Self {
+        let flat_expression = Self::from_filters(filters, filter_type);
+        match expression {
+            None => flat_expression,
+            Some(expression) => GroupFilterExpression::And(vec![flat_expression, expression]),
+        }
+    }
+
+    // Apply the default privacy policy of the group listings, used by both the child and parent canister
+    // invite only groups are left out, unless they are included or the expression explicitly filters on them
+    pub fn with_privacy_policy(self, include_invite_only: bool) -> Self {
+        if include_invite_only || self.filters_on_privacy(&PrivacyFilter::InviteOnly) {
+            return self;
+        }
+
+        GroupFilterExpression::And(vec![
+            self,
+            GroupFilterExpression::Not(Box::new(GroupFilterExpression::Filter(
+                GroupFilter::Privacy(PrivacyFilter::InviteOnly),
+            ))),
+        ])
+    }
+
+    // Check if the expression asks for groups with a privacy type, negated filters don't count
+    fn filters_on_privacy(&self, privacy_filter: &PrivacyFilter) -> bool {
+        match self {
+            GroupFilterExpression::Filter(GroupFilter::Privacy(value)) => value == privacy_filter,
+            GroupFilterExpression::Filter(_) | GroupFilterExpression::Not(_) => false,
+            GroupFilterExpression::And(expressions) | GroupFilterExpression::Or(expressions) => {
+                expressions
+                    .iter()
+                    .any(|expression| expression.filters_on_privacy(privacy_filter))
+            }
+        }
+    }
+
+    // Get all the filters used in the expression
+    pub fn filters(&self) -> Vec<&GroupFilter> {
+        match self {
+            GroupFilterExpression::Filter(filter) => vec![filter],
+            GroupFilterExpression::And(expressions) | GroupFilterExpression::Or(expressions) => {
+                expressions.iter().flat_map(|e| e.filters()).collect()
+            }
+            GroupFilterExpression::Not(expression) => expression.filters(),
+        }
+    }
+}
+
+// Equal sort keys are ordered by identifier in both directions, so the order is the same on every canister
+#[test]
+pub fn group_sort_compare() {
+    let a = Principal::from_slice(&[1]);
+    let b = Principal::from_slice(&[2]);
+    let low = GroupSortKey::MemberCount(1);
+    let high = GroupSortKey::MemberCount(2);
+
+    let asc = GroupSort::MemberCount(SortDirection::Asc);
+    assert_eq!(asc.compare((&low, &b), (&high, &a)), Ordering::Less);
+    assert_eq!(asc.compare((&low, &a), (&low, &b)), Ordering::Less);
+    assert_eq!(asc.compare((&low, &a), (&low, &a)), Ordering::Equal);
+
+    let desc = GroupSort::MemberCount(SortDirection::Desc);
+    assert_eq!(desc.compare((&low, &a), (&high, &b)), Ordering::Greater);
+    assert_eq!(desc.compare((&high, &b), (&high, &a)), Ordering::Greater);
+
+    // Relevance is best match first, distance is nearest first
+    let (best, worst) = (GroupSortKey::Relevance(3), GroupSortKey::Relevance(1));
+    assert_eq!(
+        GroupSort::Relevance.compare((&best, &b), (&worst, &a)),
+        Ordering::Less
+    );
+    let (near, far) = (
+        GroupSortKey::Distance(1.0),
+        GroupSortKey::Distance(f64::MAX),
+    );
+    let distance = GroupSort::Distance(0.0, 0.0);
+    assert_eq!(distance.compare((&near, &b), (&far, &a)), Ordering::Less);
+}
+
+// Gated filters without a sub-type match every gated group
+#[test]
+pub fn privacy_filter_matches() {
+    let neuron = Privacy::Gated(GatedType::Neuron(vec![]));
+    let token = Privacy::Gated(GatedType::Token(vec![]));
+
+    assert!(PrivacyFilter::Public.matches(&Privacy::Public));
+    assert!(!PrivacyFilter::Public.matches(&Privacy::Private));
+    assert!(PrivacyFilter::InviteOnly.matches(&Privacy::InviteOnly));
+    assert!(!PrivacyFilter::Private.matches(&Privacy::InviteOnly));
+
+    assert!(PrivacyFilter::Gated(None).matches(&neuron));
+    assert!(PrivacyFilter::Gated(None).matches(&token));
+    assert!(!PrivacyFilter::Gated(None).matches(&Privacy::Public));
+    assert!(PrivacyFilter::Gated(Some(GatedFilter::Neuron)).matches(&neuron));
+    assert!(!PrivacyFilter::Gated(Some(GatedFilter::Neuron)).matches(&token));
+    assert!(PrivacyFilter::Gated(Some(GatedFilter::Token)).matches(&token));
 }